@@ -0,0 +1,305 @@
+// Detect and unpack the different archive formats sources offer
+
+extern crate zip;
+extern crate flate2;
+extern crate tar;
+extern crate tempdir;
+
+use std::fs::File;
+use std::io::{self, Read, Cursor};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::Instant;
+use std::vec::Vec;
+use tempdir::TempDir;
+use zip::ZipArchive;
+use flate2::read::GzDecoder;
+use tar::Archive;
+
+use clippy::Limits;
+use forge::Checkout;
+use helpers::download;
+
+// ## Archive Format
+// The archive formats we know how to unpack.
+#[derive(Debug, PartialEq)]
+pub enum ArchiveFormat {
+    Zip,
+    TarGz,
+    Tar,
+}
+
+// ## Detect Format
+// Find out which format the downloaded `body` is in. We trust the content
+// more than what the server claims, so we first look at the magic bytes every
+// format starts with and only fall back to the `Content-Type` header if none
+// matched.
+pub fn detect_format(body: &[u8], content_type: Option<&str>) -> Option<ArchiveFormat> {
+    if body.starts_with(b"PK\x03\x04") {
+        Some(ArchiveFormat::Zip)
+    } else if body.starts_with(b"\x1f\x8b") {
+        Some(ArchiveFormat::TarGz)
+    } else if body.len() > 262 && &body[257..262] == b"ustar" {
+        Some(ArchiveFormat::Tar)
+    } else {
+        match content_type {
+            Some(ct) if ct.contains("zip") => Some(ArchiveFormat::Zip),
+            Some(ct) if ct.contains("gzip") => Some(ArchiveFormat::TarGz),
+            Some(ct) if ct.contains("x-tar") => Some(ArchiveFormat::Tar),
+            _ => None,
+        }
+    }
+}
+
+// ## Download And Extract
+// Given `source_url` and a target directory `tmp_dir` this function tries to
+// download the archive and unpack it there, whatever format it is in. Neither
+// the archive nor its content may take more disk space than the `limits`
+// allow, and the download has to be done by the `deadline`. Returns the
+// checkout extracted or a String with the error message of what went wrong
+// trying.
+pub fn download_and_extract(source_url: &str,
+                            tmp_dir: &TempDir,
+                            limits: &Limits,
+                            deadline: Instant)
+                            -> Result<Checkout, String> {
+    let max_bytes = limits.disk * 1024 * 1024;
+    let (body, content_type) = try!(download(source_url, max_bytes, deadline));
+    let files = try!(extract(body, content_type.as_ref().map(|ct| ct.as_str()), tmp_dir, max_bytes));
+    Ok(Checkout {
+        root: archive_root(tmp_dir.path(), &files),
+        files: files,
+    })
+}
+
+// ## Archive Root
+// Where the root of the repository is among the `files` extracted into
+// `tmp_dir`: forges usually put everything into a single top level folder,
+// e.g. `repo-<sha>/`, which then is the root. Otherwise it is `tmp_dir` itself.
+pub fn archive_root(tmp_dir: &Path, files: &[String]) -> PathBuf {
+    let mut folder: Option<PathBuf> = None;
+    for file in files {
+        let relative = match Path::new(file).strip_prefix(tmp_dir) {
+            Ok(relative) => relative,
+            Err(_) => return tmp_dir.to_path_buf(),
+        };
+        let mut components = relative.components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(top)), Some(_)) => {
+                if folder.as_ref().map_or(false, |folder| folder.as_os_str() != top) {
+                    return tmp_dir.to_path_buf();
+                }
+                folder = Some(PathBuf::from(top));
+            }
+            // a file right at the top, so there is no common folder
+            _ => return tmp_dir.to_path_buf(),
+        }
+    }
+    folder.map_or(tmp_dir.to_path_buf(), |folder| tmp_dir.join(folder))
+}
+
+// ## Extract
+// Unpack the archive in `body` into `tmp_dir`, using the appropriate extractor
+// for the detected format, and stop once more than `max_bytes` were unpacked.
+// Archives compress well, so the size of the download says little about that.
+pub fn extract(body: Vec<u8>,
+               content_type: Option<&str>,
+               tmp_dir: &TempDir,
+               max_bytes: u64)
+               -> Result<Vec<String>, String> {
+    match detect_format(&body, content_type) {
+        Some(ArchiveFormat::Zip) => unzip(body, tmp_dir, max_bytes),
+        Some(ArchiveFormat::TarGz) => {
+            // The archive is read through the gzip decoder, which gives us the
+            // plain tar stream
+            match GzDecoder::new(Cursor::new(body)) {
+                Ok(decoder) => untar(decoder, tmp_dir, max_bytes),
+                Err(error) => Err(format!("Extracting archive failed: {}", error)),
+            }
+        }
+        Some(ArchiveFormat::Tar) => untar(Cursor::new(body), tmp_dir, max_bytes),
+        // Often enough this is an error page rather than an archive
+        None => Err(String::from("Unknown archive format")),
+    }
+}
+
+// ## Too Large
+// The error we give up with once an archive unpacks to more than `max_bytes`.
+fn too_large(max_bytes: u64) -> String {
+    format!("Archive unpacks to more than the {} MB of disk space allowed",
+            max_bytes / 1024 / 1024)
+}
+
+// ## Unzip
+// Unpack the ZIP-Archive given in `zip_body` into `tmp_dir` and return the
+// list of file paths extracted, unless they add up to more than `max_bytes`.
+fn unzip(zip_body: Vec<u8>, tmp_dir: &TempDir, max_bytes: u64) -> Result<Vec<String>, String> {
+    // We pass the vector, wrapped into a Cursor (as ZipArchive requires
+    // readable trait) to ZipArchive for unzipping and processing.
+    match ZipArchive::new(Cursor::new(zip_body)) {
+        // if ZipArchive was able to read the metadata,
+        // it is time to unzip its contents
+        Ok(mut archive) => {
+            let mut paths: Vec<String> = Vec::new();
+            let mut unpacked: u64 = 0;
+            // for every file, ZipArchive identified in the response,
+            // we try to unpack it into the specified `tmp_dir`
+            for i in 0..archive.len() {
+                let mut zip_file = try!(archive.by_index(i).map_err(|error| {
+                    format!("Extracting archive failed: {}", error)
+                }));
+                let extracted_path = tmp_dir.path().join(zip_file.name());
+                let full_path = extracted_path.as_path();
+
+                // Zip uses the size of `0` to inform us that something
+                // is actually a directory. In that case, we don't try to
+                // read the content but instead set up the directory
+                // structure for it: `create_dir_all` recursively creates
+                // the directory path if not existing.
+                if zip_file.size() == 0 {
+                    try!(fs::create_dir_all(full_path).map_err(|error| {
+                        format!("Extracting archive failed: {}", error)
+                    }));
+                } else {
+                    // for any other size, we have a proper file.
+                    // copy the uncompressed content into the specified
+                    // target file – but never more than the space left,
+                    // whatever size the archive claims the file to have.
+                    let mut writer = try!(File::create(full_path).map_err(|error| {
+                        format!("Extracting archive failed: {}", error)
+                    }));
+                    let mut content = (&mut zip_file).take(max_bytes - unpacked + 1);
+                    unpacked += try!(io::copy(&mut content, &mut writer).map_err(|error| {
+                        format!("Extracting archive failed: {}", error)
+                    }));
+                    if unpacked > max_bytes {
+                        return Err(too_large(max_bytes));
+                    }
+                    // lastly, add the file path to the vectors of
+                    // paths to give back
+                    paths.push(String::from(full_path.to_string_lossy()
+                                                     .into_owned()));
+                }
+            }
+            // all went fine, all files extracted, return with `Ok`
+            // and the list of those paths
+            Ok(paths)
+        }
+        // Unfortunately we ran into a ZipArchive Error
+        Err(zip::result::ZipError::InvalidArchive(error)) |
+        Err(zip::result::ZipError::UnsupportedArchive(error)) => {
+            Err(format!("Extracting archive failed: {}", error).to_owned())
+        }
+        // ZipArchive told us about a file, which doesn't exist,
+        // this should really never happen, as we use references
+        // given by it. The only plausible cause for this is a corrupt
+        // Zip Archive – so state that.
+        Err(zip::result::ZipError::FileNotFound) => {
+            Err(String::from("Zip Archive Corrupt"))
+        }
+        Err(_) => Err(String::from("General IO Error")),
+    }
+}
+
+// ## Untar
+// Unpack the tar stream read from `reader` into `tmp_dir` and return the
+// list of file paths extracted, unless they add up to more than `max_bytes`.
+fn untar<R: Read>(reader: R, tmp_dir: &TempDir, max_bytes: u64) -> Result<Vec<String>, String> {
+    let mut archive = Archive::new(reader);
+    let mut paths: Vec<String> = Vec::new();
+    let mut unpacked: u64 = 0;
+    let entries = try!(archive.entries()
+                              .map_err(|error| format!("Extracting archive failed: {}", error)));
+    for entry in entries {
+        let mut entry = try!(entry.map_err(|error| format!("Extracting archive failed: {}", error)));
+        let is_file = entry.header().entry_type().is_file();
+        // Every entry tells us its size up front, and tar writes exactly that
+        // many bytes, so we can stop before writing the one too many.
+        unpacked += try!(entry.header()
+                              .size()
+                              .map_err(|error| format!("Extracting archive failed: {}", error)));
+        if unpacked > max_bytes {
+            return Err(too_large(max_bytes));
+        }
+        let full_path = match entry.path() {
+            Ok(path) => tmp_dir.path().join(path),
+            Err(error) => return Err(format!("Extracting archive failed: {}", error)),
+        };
+        // `unpack_in` takes care of creating the directories needed and refuses
+        // to write anything outside of `tmp_dir`, skipping such entries – so we
+        // only report the files actually written.
+        let unpacked = try!(entry.unpack_in(tmp_dir.path())
+                                 .map_err(|error| format!("Extracting archive failed: {}", error)));
+        if is_file && unpacked {
+            paths.push(full_path.to_string_lossy().into_owned());
+        }
+    }
+    // all went fine, all files extracted, return with `Ok`
+    // and the list of those paths
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{ArchiveFormat, archive_root, detect_format};
+
+    // The start of a tar archive: the file name, followed by the rest of the
+    // header, which has the `ustar` magic at 257.
+    fn tar_header() -> Vec<u8> {
+        let mut header = vec![0; 512];
+        header[..10].copy_from_slice(b"Cargo.toml");
+        header[257..262].copy_from_slice(b"ustar");
+        header
+    }
+
+    #[test]
+    fn detects_formats_by_their_magic_bytes() {
+        assert_eq!(detect_format(b"PK\x03\x04rest", None), Some(ArchiveFormat::Zip));
+        assert_eq!(detect_format(b"\x1f\x8b\x08rest", None), Some(ArchiveFormat::TarGz));
+        assert_eq!(detect_format(&tar_header(), None), Some(ArchiveFormat::Tar));
+    }
+
+    #[test]
+    fn trusts_magic_bytes_over_the_content_type() {
+        assert_eq!(detect_format(b"PK\x03\x04rest", Some("application/x-gzip")),
+                   Some(ArchiveFormat::Zip));
+        assert_eq!(detect_format(b"\x1f\x8b\x08rest", Some("application/zip")),
+                   Some(ArchiveFormat::TarGz));
+        assert_eq!(detect_format(&tar_header(), Some("application/octet-stream")),
+                   Some(ArchiveFormat::Tar));
+    }
+
+    #[test]
+    fn falls_back_to_the_content_type() {
+        assert_eq!(detect_format(b"", Some("application/zip")), Some(ArchiveFormat::Zip));
+        assert_eq!(detect_format(b"", Some("application/x-gzip")), Some(ArchiveFormat::TarGz));
+        assert_eq!(detect_format(b"", Some("application/x-tar")), Some(ArchiveFormat::Tar));
+    }
+
+    #[test]
+    fn rejects_anything_else() {
+        assert_eq!(detect_format(b"<html>Not Found</html>", Some("text/html")), None);
+        assert_eq!(detect_format(b"<html>Not Found</html>", None), None);
+    }
+
+    // The paths of the given files extracted into `/tmp/x`
+    fn extracted(files: &[&str]) -> Vec<String> {
+        files.iter().map(|file| format!("/tmp/x/{}", file)).collect()
+    }
+
+    #[test]
+    fn finds_the_single_top_level_folder() {
+        let files = extracted(&["repo-abc/rust/Cargo.toml", "repo-abc/rust/src/lib.rs", "repo-abc/README"]);
+        assert_eq!(archive_root(Path::new("/tmp/x"), &files), Path::new("/tmp/x/repo-abc"));
+    }
+
+    #[test]
+    fn takes_the_directory_itself_without_a_single_folder() {
+        let files = extracted(&["a/Cargo.toml", "b/Cargo.toml"]);
+        assert_eq!(archive_root(Path::new("/tmp/x"), &files), Path::new("/tmp/x"));
+        let files = extracted(&["repo/src/lib.rs", "Cargo.toml"]);
+        assert_eq!(archive_root(Path::new("/tmp/x"), &files), Path::new("/tmp/x"));
+    }
+}
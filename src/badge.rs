@@ -0,0 +1,179 @@
+// Render the SVG badges ourselves
+
+use std::ascii::AsciiExt;
+
+// ## Style
+// The badge styles we know how to render, named like their shields.io
+// counterparts.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Style {
+    Flat,
+    FlatSquare,
+    Plastic,
+    ForTheBadge,
+}
+
+impl Style {
+    // Find the style for the given `style`-parameter, falling back to `flat`
+    pub fn from_name(name: &str) -> Style {
+        match name {
+            "flat-square" => Style::FlatSquare,
+            "plastic" => Style::Plastic,
+            "for-the-badge" => Style::ForTheBadge,
+            _ => Style::Flat,
+        }
+    }
+}
+
+// ## Color Code
+// Map the color names we (and shields.io) use to their hex codes. Hex codes
+// given directly, like `4c1` or `#4c1`, are passed through.
+pub fn color_code(color: &str) -> Option<String> {
+    let code = match color {
+        "brightgreen" => "#4c1",
+        "green" => "#97ca00",
+        "yellowgreen" => "#a4a61d",
+        "yellow" => "#dfb317",
+        "orange" => "#fe7d37",
+        "red" => "#e05d44",
+        "blue" => "#007ec6",
+        "grey" | "gray" => "#555",
+        "lightgrey" | "lightgray" => "#9f9f9f",
+        _ => {
+            let hex = color.trim_left_matches('#');
+            return if (hex.len() == 3 || hex.len() == 6) && hex.chars().all(|c| c.is_digit(16)) {
+                Some(format!("#{}", hex))
+            } else {
+                None
+            };
+        }
+    };
+    Some(code.to_owned())
+}
+
+// ## Text Width
+// We don't have the fonts at hand to measure the text, so we estimate the
+// width in pixels of 11px Verdana – close enough for a badge.
+fn text_width(text: &str) -> u32 {
+    text.chars()
+        .map(|c| {
+            match c {
+                'i' | 'j' | 'l' | '.' | ',' | ':' | ';' | '!' | '|' | '\'' => 4,
+                ' ' | 'f' | 'r' | 't' | '(' | ')' | '[' | ']' | '-' => 5,
+                'm' | 'w' | 'M' | 'W' => 10,
+                'A'...'Z' => 8,
+                c if c.is_ascii() => 7,
+                // emoji and other wide characters
+                _ => 14,
+            }
+        })
+        .sum()
+}
+
+// ## Escape
+// Make the text safe to be put into the SVG – or any other XML – document.
+pub fn escape(text: &str) -> String {
+    text.replace("&", "&amp;")
+        .replace("<", "&lt;")
+        .replace(">", "&gt;")
+        .replace("\"", "&quot;")
+        .replace("'", "&apos;")
+}
+
+// ## Render
+// Render the badge showing `label` on the left and `message` on the right,
+// using the given colors (hex codes) and style, as an SVG document.
+pub fn render(label: &str, message: &str, color: &str, label_color: &str, style: Style) -> String {
+    // The for-the-badge style uses bigger, upper case letters with
+    // some more space around them.
+    let (label, message) = match style {
+        Style::ForTheBadge => (label.to_uppercase(), message.to_uppercase()),
+        _ => (label.to_owned(), message.to_owned()),
+    };
+    let (height, padding, font_size, text_y) = match style {
+        Style::Plastic => (18, 10, 11, 13),
+        Style::ForTheBadge => (28, 24, 10, 18),
+        _ => (20, 10, 11, 14),
+    };
+    let radius = match style {
+        Style::Flat => 3,
+        Style::Plastic => 4,
+        _ => 0,
+    };
+
+    let label_width = text_width(&label) + padding;
+    let message_width = text_width(&message) + padding;
+    let width = label_width + message_width;
+    let label_x = label_width / 2;
+    let message_x = label_width + message_width / 2;
+    let (label, message) = (escape(&label), escape(&message));
+
+    // Flat and plastic badges have a slight gradient and a shadow below
+    // the text, the other ones are plain.
+    let gradient = match style {
+        Style::Flat => {
+            "<linearGradient id=\"s\" x2=\"0\" y2=\"100%\"><stop offset=\"0\" \
+             stop-color=\"#bbb\" stop-opacity=\".1\"/><stop offset=\"1\" \
+             stop-opacity=\".1\"/></linearGradient>"
+        }
+        Style::Plastic => {
+            "<linearGradient id=\"s\" x2=\"0\" y2=\"100%\"><stop offset=\"0\" \
+             stop-color=\"#fff\" stop-opacity=\".7\"/><stop offset=\".1\" stop-color=\"#aaa\" \
+             stop-opacity=\".1\"/><stop offset=\".9\" stop-opacity=\".3\"/><stop offset=\"1\" \
+             stop-opacity=\".5\"/></linearGradient>"
+        }
+        _ => "",
+    };
+    let overlay = match gradient {
+        "" => String::new(),
+        _ => format!("<rect width=\"{}\" height=\"{}\" fill=\"url(#s)\"/>", width, height),
+    };
+    let shadow = |x: u32, text: &str| {
+        match style {
+            Style::Flat | Style::Plastic => {
+                format!("<text x=\"{}\" y=\"{}\" fill=\"#010101\" fill-opacity=\".3\">{}</text>",
+                        x,
+                        text_y + 1,
+                        text)
+            }
+            _ => String::new(),
+        }
+    };
+    let font_weight = match style {
+        Style::ForTheBadge => " font-weight=\"bold\" letter-spacing=\"1\"",
+        _ => "",
+    };
+
+    format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\">\
+             <title>{label}: {message}</title>{gradient}\
+             <clipPath id=\"r\"><rect width=\"{width}\" height=\"{height}\" rx=\"{radius}\" \
+             fill=\"#fff\"/></clipPath>\
+             <g clip-path=\"url(#r)\">\
+             <rect width=\"{label_width}\" height=\"{height}\" fill=\"{label_color}\"/>\
+             <rect x=\"{label_width}\" width=\"{message_width}\" height=\"{height}\" \
+             fill=\"{color}\"/>{overlay}</g>\
+             <g fill=\"#fff\" text-anchor=\"middle\" \
+             font-family=\"DejaVu Sans,Verdana,Geneva,sans-serif\" \
+             font-size=\"{font_size}\"{font_weight}>\
+             {label_shadow}<text x=\"{label_x}\" y=\"{text_y}\">{label}</text>\
+             {message_shadow}<text x=\"{message_x}\" y=\"{text_y}\">{message}</text>\
+             </g></svg>",
+            width = width,
+            height = height,
+            radius = radius,
+            gradient = gradient,
+            overlay = overlay,
+            label_width = label_width,
+            message_width = message_width,
+            label_color = label_color,
+            color = color,
+            font_size = font_size,
+            font_weight = font_weight,
+            label_shadow = shadow(label_x, &label),
+            message_shadow = shadow(message_x, &message),
+            label_x = label_x,
+            message_x = message_x,
+            text_y = text_y,
+            label = label,
+            message = message)
+}
@@ -5,10 +5,21 @@ extern crate rustc_serialize;
 use rustc_serialize::json::Json;
 use std::slice::SliceConcatExt;
 
-use std::process::Command;
+use std::collections::BTreeMap;
+use std::process::{Command, Stdio};
 use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
 use std::vec::Vec;
-use std::{env, fs};
+use std::{cmp, env, fmt, fs};
+
+// Unwrap the Option or return `None` from the surrounding function,
+// like `try!` does for Results.
+macro_rules! try_opt {
+    ($e:expr) => (match $e { Some(v) => v, None => return None })
+}
 
 // Enum describing the State of the Clippy result,
 // whether everything went fine or if warnings or
@@ -19,13 +30,348 @@ pub enum ClippyResult {
     WithErrors(u32, u32),
 }
 
+impl ClippyResult {
+    // Build the appropriate result for the given counts
+    pub fn from_counts(errors: u32, warnings: u32) -> ClippyResult {
+        match (errors, warnings) {
+            (0, 0) => ClippyResult::Success,
+            (0, x) => ClippyResult::WithWarnings(x),
+            _ => ClippyResult::WithErrors(errors, warnings),
+        }
+    }
+
+    // The count of errors and warnings
+    pub fn counts(&self) -> (u32, u32) {
+        match *self {
+            ClippyResult::Success => (0, 0),
+            ClippyResult::WithWarnings(warnings) => (0, warnings),
+            ClippyResult::WithErrors(errors, warnings) => (errors, warnings),
+        }
+    }
+
+    // Add up the counts of both results, e.g. for all members of a workspace
+    pub fn combine(&self, other: &ClippyResult) -> ClippyResult {
+        let (errors, warnings) = self.counts();
+        let (other_errors, other_warnings) = other.counts();
+        ClippyResult::from_counts(errors + other_errors, warnings + other_warnings)
+    }
+
+    // The status text we store and show on the badge
+    pub fn status(&self) -> String {
+        match *self {
+            ClippyResult::Success => String::from("success"),
+            ClippyResult::WithWarnings(warnings) => format!("{0} warnings", warnings),
+            ClippyResult::WithErrors(errors, _) => format!("{0} errors", errors),
+        }
+    }
+}
+
+// ## Span
+// A region in a source file a diagnostic points at, together with the
+// source lines it covers. Paths are relative to the root of the repository.
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct Span {
+    pub file: String,
+    pub line_start: u64,
+    pub line_end: u64,
+    pub column_start: u64,
+    pub column_end: u64,
+    pub text: Vec<String>,
+    pub label: Option<String>,
+}
+
+impl Span {
+    fn from_json(json: &Json) -> Option<Span> {
+        Some(Span {
+            file: try_opt!(json.find("file_name").and_then(|v| v.as_string())).to_owned(),
+            line_start: try_opt!(json.find("line_start").and_then(|v| v.as_u64())),
+            line_end: try_opt!(json.find("line_end").and_then(|v| v.as_u64())),
+            column_start: try_opt!(json.find("column_start").and_then(|v| v.as_u64())),
+            column_end: try_opt!(json.find("column_end").and_then(|v| v.as_u64())),
+            text: json.find("text")
+                      .and_then(|v| v.as_array())
+                      .map(|lines| {
+                          lines.iter()
+                               .filter_map(|line| line.find("text").and_then(|t| t.as_string()))
+                               .map(String::from)
+                               .collect()
+                      })
+                      .unwrap_or(vec![]),
+            label: json.find("label").and_then(|v| v.as_string()).map(String::from),
+        })
+    }
+}
+
+// ## Suggestion
+// A replacement for the source in `span` the compiler or clippy suggests,
+// and how confident it is that applying it is correct.
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct Suggestion {
+    pub span: Span,
+    pub replacement: String,
+    pub applicability: Option<String>,
+}
+
+// ## Diagnostic
+// A single message reported by the compiler or clippy, as parsed from its
+// JSON output: the lint name (or error code), the level and message, the
+// primary span it points at, any notes attached and suggested replacements.
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct Diagnostic {
+    pub code: Option<String>,
+    pub level: String,
+    pub message: String,
+    pub span: Option<Span>,
+    pub children: Vec<Diagnostic>,
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl Diagnostic {
+    // Parse one line of rustc's `--error-format=json` output
+    pub fn from_json(json: &Json) -> Option<Diagnostic> {
+        let spans: &[Json] = json.find("spans")
+                                 .and_then(|v| v.as_array())
+                                 .map(|v| v.as_slice())
+                                 .unwrap_or(&[]);
+        let children: &[Json] = json.find("children")
+                                    .and_then(|v| v.as_array())
+                                    .map(|v| v.as_slice())
+                                    .unwrap_or(&[]);
+        let children: Vec<Diagnostic> = children.iter()
+                                                .filter_map(Diagnostic::from_json)
+                                                .collect();
+
+        // Suggestions are found in the spans of the diagnostic – those of its
+        // children, usually the `help`s, stay with them.
+        let mut suggestions: Vec<Suggestion> = Vec::new();
+        for span in spans {
+            if let (Some(parsed), Some(replacement)) =
+                   (Span::from_json(span),
+                    span.find("suggested_replacement").and_then(|v| v.as_string())) {
+                suggestions.push(Suggestion {
+                    span: parsed,
+                    replacement: replacement.to_owned(),
+                    applicability: span.find("suggestion_applicability")
+                                       .and_then(|v| v.as_string())
+                                       .map(String::from),
+                });
+            }
+        }
+
+        Some(Diagnostic {
+            code: json.find_path(&["code", "code"]).and_then(|v| v.as_string()).map(String::from),
+            level: try_opt!(json.find("level").and_then(|v| v.as_string())).to_owned(),
+            message: try_opt!(json.find("message").and_then(|v| v.as_string())).to_owned(),
+            // the primary span is the one the diagnostic is really about,
+            // if none is marked, we take the first.
+            span: spans.iter()
+                       .find(|span| span.find("is_primary") == Some(&Json::Boolean(true)))
+                       .or(spans.first())
+                       .and_then(Span::from_json),
+            children: children,
+            suggestions: suggestions,
+        })
+    }
+
+    // Rewrite the paths of all spans with the given function, e.g. to make
+    // them relative to the root of the repository.
+    pub fn map_paths<F>(&mut self, f: &F)
+        where F: Fn(&str) -> String
+    {
+        if let Some(ref mut span) = self.span {
+            span.file = f(&span.file);
+        }
+        for suggestion in self.suggestions.iter_mut() {
+            suggestion.span.file = f(&suggestion.span.file);
+        }
+        for child in self.children.iter_mut() {
+            child.map_paths(f);
+        }
+    }
+}
+
+// ## Count By Lint
+// Count the warnings and errors reported by every lint (or error code).
+// Diagnostics without any code are counted by their level instead.
+pub fn count_by_lint(diagnostics: &[Diagnostic]) -> BTreeMap<String, u32> {
+    let mut counts: BTreeMap<String, u32> = BTreeMap::new();
+    for diagnostic in diagnostics.iter().filter(|d| d.level == "warning" || d.level == "error") {
+        let name = diagnostic.code.clone().unwrap_or(diagnostic.level.clone());
+        *counts.entry(name).or_insert(0) += 1;
+    }
+    counts
+}
+
+// ## Help URL
+// Where to learn more about the lint – or error – the diagnostic is about:
+// error codes are explained in the error index, clippy's lints – those named
+// `clippy::…` – in its lint list and the compiler's own lints in the listing
+// of the rustc book.
+pub fn help_url(code: &str) -> String {
+    if is_error_code(code) {
+        format!("https://doc.rust-lang.org/error-index.html#{}", code)
+    } else if code.starts_with("clippy::") {
+        format!("https://rust-lang.github.io/rust-clippy/master/index.html#{}",
+                code.trim_left_matches("clippy::"))
+    } else {
+        String::from("https://doc.rust-lang.org/rustc/lints/listing/index.html")
+    }
+}
+
+// ## Is Error Code
+// Whether the code is one of the compiler's error codes, e.g. `E0308`, rather
+// than the name of a lint.
+pub fn is_error_code(code: &str) -> bool {
+    code.starts_with('E') && code.len() == 5 && code[1..].chars().all(|c| c.is_digit(10))
+}
+
+// ## Toolchain
+// The versions of the tools we are running clippy with, as reported by
+// themselves, e.g. `("rustc", "rustc 1.9.0-nightly (...)")`.
+pub fn toolchain() -> Vec<(String, String)> {
+    ["rustc", "cargo"]
+        .iter()
+        .map(|tool| {
+            let version = Command::new(tool)
+                              .arg("--version")
+                              .output()
+                              .ok()
+                              .and_then(|output| String::from_utf8(output.stdout).ok())
+                              .map(|version| version.trim().to_owned())
+                              .unwrap_or(String::from("unknown"));
+            (tool.to_string(), version)
+        })
+        .collect()
+}
+
+// ## Report
+// Everything we learned from running clippy: the overall result and all the
+// diagnostics reported.
+pub struct Report {
+    pub result: ClippyResult,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+// ## Run Error
+// Why running clippy didn't give us a report: either it failed – be it
+// clippy, cargo or firejail – or we had to stop it, as it took longer than
+// the number of seconds allowed.
+pub enum RunError {
+    Failed(String),
+    Timeout(u64),
+}
+
+impl RunError {
+    // The status text we store and show on the badge
+    pub fn status(&self) -> String {
+        match *self {
+            RunError::Failed(_) => String::from("failed"),
+            RunError::Timeout(_) => String::from("timeout"),
+        }
+    }
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RunError::Failed(ref error) => write!(f, "{}", error),
+            RunError::Timeout(seconds) => write!(f, "Timed out after {} seconds", seconds),
+        }
+    }
+}
+
+// ## Limits
+// The resources linting a job may use: the wall-clock time for all of its
+// clippy runs together, after which we kill them, plus the memory and CPU
+// time of every process, which `prlimit` enforces inside the sandbox, and the
+// disk space of the whole checkout, including everything built. Configured
+// via the `LINT_TIMEOUT` (seconds), `LINT_MEMORY` (MB), `LINT_CPU` (seconds)
+// and `LINT_DISK` (MB) environment variables.
+pub struct Limits {
+    pub timeout: u64,
+    pub memory: u64,
+    pub cpu: u64,
+    pub disk: u64,
+}
+
+// How often we look at the disk space used while clippy is running
+const DISK_CHECK_SECONDS: u64 = 5;
+
+impl Limits {
+    pub fn from_env() -> Limits {
+        let var = |name: &str, default: u64| {
+            env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+        };
+        Limits {
+            timeout: var("LINT_TIMEOUT", 600),
+            memory: var("LINT_MEMORY", 2048),
+            cpu: var("LINT_CPU", 600),
+            disk: var("LINT_DISK", 1024),
+        }
+    }
+
+    // When a job started now has to be done
+    pub fn deadline(&self) -> Instant {
+        Instant::now() + Duration::from_secs(self.timeout)
+    }
+
+    // The `prlimit` command limiting the memory and CPU time of the command
+    // following it – and all its children
+    fn prlimit_args(&self) -> Vec<String> {
+        vec![String::from("prlimit"),
+             format!("--as={}", self.memory * 1024 * 1024),
+             format!("--cpu={}", self.cpu),
+             String::from("--")]
+    }
+}
+
+// ## Disk Usage
+// The bytes taken by all files below `path`, not following symlinks.
+pub fn disk_usage(path: &Path) -> u64 {
+    match fs::symlink_metadata(path) {
+        Ok(ref metadata) if metadata.is_dir() => {
+            fs::read_dir(path)
+                .map(|entries| {
+                    entries.filter_map(|entry| entry.ok())
+                           .map(|entry| disk_usage(&entry.path()))
+                           .fold(0, |sum, size| sum + size)
+                })
+                .unwrap_or(0)
+        }
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    }
+}
+
+// ## Shut Down
+// Stop the sandbox with the given `pid` – firejail, cargo and whatever a build
+// script might have spawned – which closes its output and lets us carry on.
+fn shut_down(pid: u32) {
+    let _ = Command::new("firejail").arg(format!("--shutdown={}", pid)).status();
+    let _ = Command::new("kill").args(&["-KILL", &pid.to_string()]).status();
+}
+
 // ## Run
 // Run clippy in the specified Path. We expect there to be a Cargo.toml file, checking for
-// that should have happened already. While calculating the `ClippyResult`, inform about
-// the current process via the `logger` function.
-pub fn run<F>(path: &Path, logger: F) -> Result<ClippyResult, String>
+// that should have happened already. The `sandbox` is the directory the process gets
+// access to, usually the root of the checkout, so workspace members can reach their
+// siblings. While calculating the `Report`, inform about the current process via
+// the `logger` function. The process is confined to the `limits` given and stopped
+// once the `deadline` of the job has passed.
+pub fn run<F>(sandbox: &Path,
+              path: &Path,
+              limits: &Limits,
+              deadline: Instant,
+              logger: F)
+              -> Result<Report, RunError>
     where F: Fn(&str)
 {
+    // Earlier members may have used up all the time there was
+    if Instant::now() >= deadline {
+        let error = RunError::Timeout(limits.timeout);
+        logger(&format!("-----\n{}", error));
+        return Err(error);
+    }
 
     // Find the _local_ clippy we are shipping with the clippy-service
     // and append that to the cargo rustc process
@@ -40,17 +386,19 @@ pub fn run<F>(path: &Path, logger: F) -> Result<ClippyResult, String>
     // the `--privileged` mode.
     // Lastly allow it to access the usually unaccessible dependencies, where
     // our clippy lib is stored.
-    match Command::new("firejail")
+    let child = Command::new("firejail")
               .args(&["--profile=/etc/firejail/cargo.profile",
                       "--force",
                       format!("--whitelist={}",
-                      &path.to_string_lossy().into_owned()).as_str(),
+                      &sandbox.to_string_lossy().into_owned()).as_str()])
+    // Limit the memory and CPU time the process may use.
+              .args(&limits.prlimit_args())
 
     // The command we want to run is `cargo rustc` with the extra compiler
     // plugin for clippy which can be found at the library path passed after
     // `-L`. Secondly we need rustc to report errors in the `json`-format (new
     // nightly feature), so we can parse it later.
-                      "cargo",
+              .args(&["cargo",
                       "rustc",
                       "--",
                       "-L",
@@ -62,66 +410,115 @@ pub fn run<F>(path: &Path, logger: F) -> Result<ClippyResult, String>
                       "--error-format=json"])
     // Run it from the directory passed in and keep the output
               .current_dir(path)
-              .output() {
+              .stdout(Stdio::piped())
+              .stderr(Stdio::piped())
+              .spawn();
+    let child = match child {
+        Ok(child) => child,
+        Err(error) => return Err(RunError::Failed(format!("Running Clippy failed: {}", error))),
+    };
+
+    // Keep an eye on the clock and the disk space used: if the process isn't
+    // done in time or fills up the disk, shut down the whole sandbox and
+    // remember why we did.
+    let pid = child.id();
+    let stopped: Arc<Mutex<Option<RunError>>> = Arc::new(Mutex::new(None));
+    let (done, finished) = mpsc::channel::<()>();
+    let watchdog = {
+        let stopped = stopped.clone();
+        let sandbox = sandbox.to_path_buf();
+        let (timeout, disk) = (limits.timeout, limits.disk);
+        thread::spawn(move || {
+            loop {
+                let now = Instant::now();
+                if now >= deadline {
+                    *stopped.lock().unwrap() = Some(RunError::Timeout(timeout));
+                    shut_down(pid);
+                    break;
+                }
+                let wait = cmp::min(deadline - now, Duration::from_secs(DISK_CHECK_SECONDS));
+                match finished.recv_timeout(wait) {
+                    Err(RecvTimeoutError::Timeout) => {
+                        if disk_usage(&sandbox) > disk * 1024 * 1024 {
+                            *stopped.lock().unwrap() =
+                                Some(RunError::Failed(format!("Used more than the {} MB of disk \
+                                                               space allowed",
+                                                              disk)));
+                            shut_down(pid);
+                            break;
+                        }
+                    }
+                    _ => break,
+                }
+            }
+        })
+    };
+    let output = child.wait_with_output();
+    let _ = done.send(());
+    let _ = watchdog.join();
+
+    match output {
         Ok(output) => {
             // First and foremost: read and log the output, so that
             // humans looking at it can use it. See how we are using the
             // logger-function to do that?
-            let stdout = String::from_utf8(output.stdout).unwrap();
-            let stderr = String::from_utf8(output.stderr).unwrap();
+            let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+            let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
             logger(&format!("----- stdout:\n{}", &stdout));
             logger(&format!("----- stderr:\n{}", &stderr));
-            let mut warnings = 0;
-            let mut errors = 0;
+
+            // If we had to stop it, whatever was reported is incomplete.
+            if let Some(error) = stopped.lock().unwrap().take() {
+                logger(&format!("-----\n{}", error));
+                return Err(error);
+            }
+
             // Next up, we need to parse the outpuf from stderr, where
             // clippy and the compiler might report errors to us. There is
             // one error per line, which is why we split it into lines. We
             // then use `filter_map` to find all those lines we can decode
-            // from JSON
-            let messages: Vec<String> = stderr.split('\n')
-                                            .filter_map(|line| Json::from_str(&line).ok())
-                                            .filter_map(|json| {
-                                                // and then `filter_map` those into
-                                                // the warnings and errors we care about,
-                                                // while also updating the local count
-                                                // of both.
-                                                let obj = json.as_object().unwrap();
-                                                match obj.get("level") {
-                                                    Some(&Json::String(ref level)) => {
-                                                        if level == "warning" {
-                                                            warnings += 1;
-                                                        } else if level == "error" {
-                                                            errors += 1;
-                                                        }
-                                                        Some(format!("{level}: {msg}",
-                                                                     level = level,
-                                                                     msg = obj.get("message")
-                                                                              .unwrap()))
-                                                    }
-                                                    _ => None,
-                                                }
-                                            })
-                                            // The collect executes this iterative into
-                                            // a vector of results. We can now log to the
-                                            // viewer.
-                                            .collect();
+            // from JSON and parse them into `Diagnostic`s.
+            let diagnostics: Vec<Diagnostic> = stderr.split('\n')
+                                                     .filter_map(|line| Json::from_str(&line).ok())
+                                                     .filter_map(|json| Diagnostic::from_json(&json))
+                                                     .collect();
+
+            // count the warnings and errors we care about
+            let warnings = diagnostics.iter().filter(|d| d.level == "warning").count() as u32;
+            let errors = diagnostics.iter().filter(|d| d.level == "error").count() as u32;
 
+            let messages: Vec<String> = diagnostics.iter()
+                                                   .map(|d| format!("{}: {}", d.level, d.message))
+                                                   .collect();
             logger(&format!("-----\nMessages identified:\n {}", messages.join("\n")));
 
             // Next parse the count of errors and warnings
             // and wrap that into the appropriate `ClippyResult`
             if output.status.success() {
-                match (errors, warnings) {
-                    (0, 0) => Ok(ClippyResult::Success),
-                    (0, x) => Ok(ClippyResult::WithWarnings(x)),
-                    _ => Ok(ClippyResult::WithErrors(errors, warnings))
-                }
+                Ok(Report {
+                    result: ClippyResult::from_counts(errors, warnings),
+                    diagnostics: diagnostics,
+                })
             // Or report an Error if clippy (or firejail) failed to execute
             } else {
-                Err(format!("Clippy failed with Error code: {}",
-                            output.status.code().unwrap_or(-999)))
+                Err(RunError::Failed(format!("Clippy failed with Error code: {}",
+                                             output.status.code().unwrap_or(-999))))
             }
         }
-        Err(error) => Err(format!("Running Clippy failed: {}", error)),
+        Err(error) => Err(RunError::Failed(format!("Running Clippy failed: {}", error))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::help_url;
+
+    #[test]
+    fn links_errors_clippy_lints_and_rustc_lints_to_their_docs() {
+        assert_eq!(help_url("E0308"), "https://doc.rust-lang.org/error-index.html#E0308");
+        assert_eq!(help_url("clippy::needless_return"),
+                   "https://rust-lang.github.io/rust-clippy/master/index.html#needless_return");
+        assert_eq!(help_url("unused_variables"),
+                   "https://doc.rust-lang.org/rustc/lints/listing/index.html");
     }
 }
@@ -0,0 +1,231 @@
+// Crates Registry Backend code – for linting crates as they were published
+
+extern crate rustc_serialize;
+extern crate hyper;
+extern crate semver;
+
+use std::env;
+use rustc_serialize::json::Json;
+use hyper::client::Client;
+use semver::Version;
+
+use forge::{Forge, ResolveError, fetch_json};
+use helpers::{fetch, http_client};
+
+// ## Crates
+// The Forge implementation for the crates registry. Projects are crate names
+// and instead of a SHA, we are dealing with the published versions, which we
+// look up in the registry index.
+pub struct Crates {
+    // e.g. `https://index.crates.io`
+    index_url: String,
+    // e.g. `https://crates.io`
+    base_url: String,
+}
+
+impl Crates {
+    // Create the registry source for the index configured in the
+    // `CRATES_INDEX_URL` environment variable, so a local mirror of the index
+    // can be used, falling back to the one of crates.io if not found. Links
+    // for humans point to the registry at `CRATES_REGISTRY_URL`, or crates.io.
+    pub fn from_env() -> Crates {
+        let var = |name: &str, default: &str| {
+            env::var(name).unwrap_or(default.to_owned()).trim_right_matches('/').to_owned()
+        };
+        Crates {
+            index_url: var("CRATES_INDEX_URL", "https://index.crates.io"),
+            base_url: var("CRATES_REGISTRY_URL", "https://crates.io"),
+        }
+    }
+
+    // All entries of the crate in the index, one line of JSON for every
+    // version published.
+    fn index_entries(&self, name: &str) -> Result<Vec<Json>, ResolveError> {
+        let index_file = format!("{0}/{1}/{2}",
+                                 self.index_url,
+                                 prefix(&name.to_lowercase()),
+                                 name.to_lowercase());
+        let hyper_client: Client = http_client();
+        let body = try!(fetch(&hyper_client, &index_file)
+                            .ok_or(ResolveError::NotFound(format!("Couldn't find {}", index_file))));
+        let entries: Vec<Json> = body.lines()
+                                     .filter_map(|line| Json::from_str(line).ok())
+                                     .collect();
+        if entries.is_empty() {
+            warn!("{}: no versions found in index: {}", &index_file, &body);
+            Err(ResolveError::NotFound(format!("Couldn't find {}", index_file)))
+        } else {
+            Ok(entries)
+        }
+    }
+}
+
+// ## Prefix
+// Crates are sorted into folders of the index by the first letters of their
+// name: `1/`, `2/` and `3/{first letter}/` for short names, otherwise
+// `{first two}/{next two}/`.
+fn prefix(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let part = |from: usize, to: usize| chars[from..to].iter().cloned().collect::<String>();
+    match chars.len() {
+        1 => String::from("1"),
+        2 => String::from("2"),
+        3 => format!("3/{}", part(0, 1)),
+        _ => format!("{}/{}", part(0, 2), part(2, 4)),
+    }
+}
+
+// ## Newest Version
+// Given the entries of the index – one per version published – find the
+// newest one that hasn't been yanked, preferring proper releases over
+// pre-releases.
+fn newest_version(entries: &[Json]) -> Option<Version> {
+    let mut available: Vec<Version> = entries.iter()
+                                             .filter(|v| {
+                                                 v.find("yanked") != Some(&Json::Boolean(true))
+                                             })
+                                             .filter_map(|v| v.find("vers"))
+                                             .filter_map(|vers| vers.as_string())
+                                             .filter_map(|vers| Version::parse(vers).ok())
+                                             .collect();
+    available.sort();
+    let newest_release = available.iter().rev().find(|v| v.pre.is_empty()).cloned();
+    newest_release.or(available.pop())
+}
+
+// ## Download URL
+// Fill in the `dl` template of the index' `config.json`. If it has none of
+// the markers, the crate and version are appended, as cargo does.
+fn download_url(template: &str, name: &str, version: &str, checksum: &str) -> String {
+    let markers = ["{crate}", "{version}", "{prefix}", "{lowerprefix}", "{sha256-checksum}"];
+    if markers.iter().any(|marker| template.contains(marker)) {
+        template.replace("{crate}", name)
+                .replace("{version}", version)
+                .replace("{prefix}", &prefix(name))
+                .replace("{lowerprefix}", &prefix(&name.to_lowercase()))
+                .replace("{sha256-checksum}", checksum)
+    } else {
+        format!("{0}/{1}/{2}/download", template.trim_right_matches('/'), name, version)
+    }
+}
+
+impl Forge for Crates {
+    fn name(&self) -> &'static str {
+        "crates"
+    }
+
+    // There are no branches on the registry, so whatever we are asked for,
+    // we look up the newest version of the crate in the registry index.
+    fn resolve_sha(&self, project: &str, _branch: &str) -> Result<String, ResolveError> {
+        let entries = try!(self.index_entries(project));
+        match newest_version(&entries) {
+            Some(version) => Ok(version.to_string()),
+            None => Err(ResolveError::NotFound(format!("No version of {} found", project))),
+        }
+    }
+
+    fn default_branch(&self) -> &'static str {
+        "newest"
+    }
+
+    // Versions are typed in by hand, so we make sure the index knows about
+    // the one asked for – yanked or not, it can still be downloaded.
+    fn check_sha(&self, project: &str, sha: &str) -> Result<(), ResolveError> {
+        let entries = try!(self.index_entries(project));
+        if entries.iter().any(|entry| entry.find("vers").and_then(|v| v.as_string()) == Some(sha)) {
+            Ok(())
+        } else {
+            Err(ResolveError::NotFound(format!("No version {} of {} found", sha, project)))
+        }
+    }
+
+    // Where to download the `.crate` file of that version – a gzip'd tar
+    // archive – is configured by the index itself. Only if its template asks
+    // for the checksum, we need to look that up in the index, too.
+    fn archive_url(&self, project: &str, sha: &str) -> String {
+        let config_url = format!("{}/config.json", self.index_url);
+        let template = fetch_json(&config_url)
+                           .ok()
+                           .and_then(|config| config.find("dl").and_then(|dl| dl.as_string()).map(String::from))
+                           .unwrap_or_else(|| {
+                               warn!("{}: no download URL configured, using crates.io", config_url);
+                               String::from("https://static.crates.io/crates")
+                           });
+        let checksum = if template.contains("{sha256-checksum}") {
+            self.index_entries(project)
+                .ok()
+                .and_then(|entries| {
+                    entries.iter()
+                           .find(|entry| entry.find("vers").and_then(|v| v.as_string()) == Some(sha))
+                           .and_then(|entry| entry.find("cksum").and_then(|c| c.as_string()))
+                           .map(String::from)
+                })
+                .unwrap_or(String::new())
+        } else {
+            String::new()
+        };
+        download_url(&template, project, sha, &checksum)
+    }
+
+    fn display_url(&self, project: &str, sha: &str) -> String {
+        format!("{0}/crates/{1}/{2}", self.base_url, project, sha)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::Json;
+
+    use super::{download_url, newest_version, prefix};
+
+    // The index entries for the given versions, with whether they are yanked
+    fn entries(versions: &[(&str, bool)]) -> Vec<Json> {
+        versions.iter()
+                .map(|&(vers, yanked)| {
+                    Json::from_str(&format!("{{\"name\":\"foo\",\"vers\":\"{}\",\"yanked\":{}}}",
+                                            vers,
+                                            yanked))
+                        .unwrap()
+                })
+                .collect()
+    }
+
+    #[test]
+    fn sorts_crates_into_the_index_folders() {
+        assert_eq!(prefix("a"), "1");
+        assert_eq!(prefix("ab"), "2");
+        assert_eq!(prefix("abc"), "3/a");
+        assert_eq!(prefix("serde"), "se/rd");
+        assert_eq!(prefix("Serde"), "Se/rd");
+    }
+
+    #[test]
+    fn finds_the_newest_version_that_is_not_yanked() {
+        let found = newest_version(&entries(&[("0.1.0", false), ("0.10.0", false), ("0.2.0", false)]));
+        assert_eq!(found.unwrap().to_string(), "0.10.0");
+        let found = newest_version(&entries(&[("1.0.0", false), ("1.1.0", true)]));
+        assert_eq!(found.unwrap().to_string(), "1.0.0");
+        assert!(newest_version(&entries(&[("1.0.0", true)])).is_none());
+    }
+
+    #[test]
+    fn prefers_releases_over_pre_releases() {
+        let found = newest_version(&entries(&[("1.0.0", false), ("2.0.0-beta.1", false)]));
+        assert_eq!(found.unwrap().to_string(), "1.0.0");
+        let found = newest_version(&entries(&[("2.0.0-alpha", false), ("2.0.0-beta.1", false)]));
+        assert_eq!(found.unwrap().to_string(), "2.0.0-beta.1");
+    }
+
+    #[test]
+    fn fills_in_the_download_template() {
+        assert_eq!(download_url("https://static.crates.io/crates/", "serde", "1.0.0", ""),
+                   "https://static.crates.io/crates/serde/1.0.0/download");
+        assert_eq!(download_url("https://mirror/{lowerprefix}/{crate}/{crate}-{version}.crate",
+                                "Serde",
+                                "1.0.0",
+                                ""),
+                   "https://mirror/se/rd/Serde/Serde-1.0.0.crate");
+        assert_eq!(download_url("https://mirror/{prefix}/{sha256-checksum}", "Serde", "1.0.0", "abc"),
+                   "https://mirror/Se/rd/abc");
+    }
+}
@@ -0,0 +1,135 @@
+// Compare the diagnostics of two runs, to learn which are new and which fixed
+
+use std::collections::HashMap;
+use std::slice::SliceConcatExt;
+use std::vec::Vec;
+
+use clippy::{ClippyResult, Diagnostic};
+
+// ## Fingerprint
+// What identifies a diagnostic across commits: its lint (or level, if it has
+// none), the file and the source it points at, with all whitespace collapsed.
+// Line numbers can't be part of it, as they change with every line added
+// further up.
+pub fn fingerprint(diagnostic: &Diagnostic) -> (String, String, String) {
+    let lint = diagnostic.code.clone().unwrap_or(diagnostic.level.clone());
+    match diagnostic.span {
+        Some(ref span) => {
+            let snippet = span.text
+                              .iter()
+                              .flat_map(|line| line.split_whitespace())
+                              .collect::<Vec<&str>>()
+                              .join(" ");
+            (lint, span.file.clone(), snippet)
+        }
+        None => (lint, String::new(), diagnostic.message.clone()),
+    }
+}
+
+// ## New Diagnostics
+// The warnings and errors of `head` which can't be found in `base`. The same
+// issue may be reported several times in a file, so we match them up one by
+// one: if `head` has three of them and `base` two, one is new.
+pub fn new_diagnostics(base: &[Diagnostic], head: &[Diagnostic]) -> Vec<Diagnostic> {
+    let relevant = |diagnostic: &&Diagnostic| {
+        diagnostic.level == "warning" || diagnostic.level == "error"
+    };
+    let mut known: HashMap<(String, String, String), usize> = HashMap::new();
+    for diagnostic in base.iter().filter(&relevant) {
+        *known.entry(fingerprint(diagnostic)).or_insert(0) += 1;
+    }
+
+    let mut new: Vec<Diagnostic> = Vec::new();
+    for diagnostic in head.iter().filter(&relevant) {
+        let count = known.entry(fingerprint(diagnostic)).or_insert(0);
+        if *count > 0 {
+            *count -= 1;
+        } else {
+            new.push(diagnostic.clone());
+        }
+    }
+    new
+}
+
+// ## Fixed Diagnostics
+// The warnings and errors of `base` which are gone in `head`.
+pub fn fixed_diagnostics(base: &[Diagnostic], head: &[Diagnostic]) -> Vec<Diagnostic> {
+    new_diagnostics(head, base)
+}
+
+// ## New Status
+// The status text for the given new diagnostics, e.g. `3 new warnings`, or
+// `success` if there are none.
+pub fn new_status(new: &[Diagnostic]) -> String {
+    let warnings = new.iter().filter(|d| d.level == "warning").count() as u32;
+    let errors = new.iter().filter(|d| d.level == "error").count() as u32;
+    match ClippyResult::from_counts(errors, warnings) {
+        ClippyResult::Success => String::from("success"),
+        ClippyResult::WithWarnings(warnings) => format!("{} new warnings", warnings),
+        ClippyResult::WithErrors(errors, _) => format!("{} new errors", errors),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fixed_diagnostics, new_diagnostics};
+    use clippy::{Diagnostic, Span};
+
+    // A warning of `lint` in `file` at `line`, pointing at the `source`
+    fn warning(lint: &str, file: &str, line: u64, source: &str) -> Diagnostic {
+        Diagnostic {
+            code: Some(lint.to_owned()),
+            level: String::from("warning"),
+            message: format!("{} fired", lint),
+            span: Some(Span {
+                file: file.to_owned(),
+                line_start: line,
+                line_end: line,
+                column_start: 1,
+                column_end: source.len() as u64 + 1,
+                text: vec![source.to_owned()],
+                label: None,
+            }),
+            children: vec![],
+            suggestions: vec![],
+        }
+    }
+
+    #[test]
+    fn matches_a_warning_on_a_shifted_line() {
+        let base = vec![warning("needless_return", "src/lib.rs", 10, "    return x;")];
+        let head = vec![warning("needless_return", "src/lib.rs", 14, "        return  x;")];
+        assert!(new_diagnostics(&base, &head).is_empty());
+        assert!(fixed_diagnostics(&base, &head).is_empty());
+    }
+
+    #[test]
+    fn counts_every_extra_occurrence_once() {
+        let base = vec![warning("needless_return", "src/lib.rs", 10, "return x;"),
+                        warning("needless_return", "src/lib.rs", 20, "return x;")];
+        let head = vec![warning("needless_return", "src/lib.rs", 10, "return x;"),
+                        warning("needless_return", "src/lib.rs", 20, "return x;"),
+                        warning("needless_return", "src/lib.rs", 30, "return x;"),
+                        warning("needless_return", "src/lib.rs", 40, "return x;")];
+        assert_eq!(new_diagnostics(&base, &head).len(), 2);
+        assert!(fixed_diagnostics(&base, &head).is_empty());
+    }
+
+    #[test]
+    fn counts_a_changed_snippet_as_new() {
+        let base = vec![warning("needless_return", "src/lib.rs", 10, "return x;")];
+        let head = vec![warning("needless_return", "src/lib.rs", 10, "return y;")];
+        let new = new_diagnostics(&base, &head);
+        assert_eq!(new.len(), 1);
+        assert_eq!(new[0].span.as_ref().unwrap().text, vec![String::from("return y;")]);
+        assert_eq!(fixed_diagnostics(&base, &head).len(), 1);
+    }
+
+    #[test]
+    fn tells_lints_and_files_apart() {
+        let base = vec![warning("needless_return", "src/lib.rs", 10, "return x;")];
+        let head = vec![warning("let_and_return", "src/lib.rs", 10, "return x;"),
+                        warning("needless_return", "src/main.rs", 10, "return x;")];
+        assert_eq!(new_diagnostics(&base, &head).len(), 2);
+    }
+}
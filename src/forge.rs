@@ -0,0 +1,219 @@
+// The abstraction over the different places, code can be fetched from.
+
+extern crate rustc_serialize;
+extern crate hyper;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+use rustc_serialize::json::Json;
+use hyper::client::Client;
+use tempdir::TempDir;
+
+use helpers::{fetch, http_client, setup_redis};
+use archive::download_and_extract;
+use clippy::Limits;
+use jobs::{Job, enqueue};
+use github::GitHub;
+use gitlab::GitLab;
+use git::Git;
+use crates::Crates;
+
+// ## Resolve Error
+// Looking up a branch can fail for two reasons: the source tells us it
+// doesn't know about it, or it says something we don't understand.
+pub enum ResolveError {
+    NotFound(String),
+    BadResponse(String),
+}
+
+// ## Checkout
+// The sources of a project as we put them on disk: the directory the root of
+// the repository ended up in – which paths in the results are relative to –
+// and all files found below it.
+pub struct Checkout {
+    pub root: PathBuf,
+    pub files: Vec<String>,
+}
+
+// ## Forge
+// A Forge is any service hosting projects, that we can ask for the current
+// SHA of a branch and for an archive of the source at a given SHA. Projects
+// are identified by their path on the forge, e.g. `user/repo`.
+// All routing, the redis key scheme, badge rendering and the logs are
+// shared, so adding a new source only requires implementing this trait.
+pub trait Forge: Send + Sync {
+    // The name used for mounting the routes and in the redis keys,
+    // e.g. `github`.
+    fn name(&self) -> &'static str;
+
+    // Expand a branch name into the SHA it currently points to.
+    fn resolve_sha(&self, project: &str, branch: &str) -> Result<String, ResolveError>;
+
+    // The branch we look at if none is asked for. It becomes part of the
+    // redis keys of the branch, so it should name what it stands for.
+    fn default_branch(&self) -> &'static str {
+        "master"
+    }
+
+    // Make sure the SHA asked for exists before we queue a job for it, where
+    // the forge can tell cheaply. Most can't, and a job for an unknown SHA
+    // simply fails, so by default every SHA is fine.
+    fn check_sha(&self, _project: &str, _sha: &str) -> Result<(), ResolveError> {
+        Ok(())
+    }
+
+    // Find the SHAs of the base and the head of the pull request with the
+    // given number. Not every forge has those, so by default there are none.
+    fn resolve_pull(&self, project: &str, number: &str) -> Result<(String, String), ResolveError> {
+        Err(ResolveError::NotFound(format!("{} has no pull request {}", project, number)))
+    }
+
+    // The URL to download the archive of the project at the given SHA from.
+    fn archive_url(&self, project: &str, sha: &str) -> String;
+
+    // The URL for humans to look at the project at the given SHA.
+    fn display_url(&self, project: &str, sha: &str) -> String;
+
+    // The URL for humans to look at the given line of a file of the project
+    // at the given SHA, if the forge offers one.
+    fn file_url(&self, _project: &str, _sha: &str, _file: &str, _line: u64) -> Option<String> {
+        None
+    }
+
+    // Put the sources of the project at the given SHA into the `temp_dir` and
+    // return where they ended up, giving up once they take more disk
+    // space than the `limits` allow or the `deadline` of the job passes.
+    // By default we download and extract the archive from `archive_url`,
+    // whichever format it is in.
+    fn checkout(&self,
+                project: &str,
+                sha: &str,
+                temp_dir: &TempDir,
+                limits: &Limits,
+                deadline: Instant,
+                logger: &Fn(&str))
+                -> Result<Checkout, String> {
+        let archive_url = self.archive_url(project, sha);
+        logger(&format!("Fetching {}", &archive_url));
+        download_and_extract(&archive_url, temp_dir, limits, deadline)
+    }
+
+    // Report the `status` of linting the project at the given SHA back to
+    // the forge under the given `context` – e.g. as a commit status – linking
+    // to the `target_url` for details. Most forges don't support that, so by
+    // default there is nothing to do.
+    fn report_status(&self,
+                     _project: &str,
+                     _sha: &str,
+                     _context: &str,
+                     _status: &str,
+                     _target_url: Option<&str>)
+                     -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// ## Base Key
+// The key layout shared by all forges – `forge/project:sha` – which is
+// prefixed with `result/`, `log/` and so on to find the specific entries
+// in redis.
+pub fn base_key(forge: &Forge, project: &str, sha: &str) -> String {
+    format!("{0}/{1}:{2}", forge.name(), project, sha)
+}
+
+// ## Lint Key
+// The key the results of linting are stored under. If a specific `manifest`
+// path was asked for, it becomes part of the key – `forge/project:sha:manifest`
+// – so different crates in one repository get separate results.
+pub fn lint_key(forge: &Forge, project: &str, sha: &str, manifest: Option<&str>) -> String {
+    match manifest {
+        Some(manifest) => format!("{0}:{1}", base_key(forge, project, sha), manifest),
+        None => base_key(forge, project, sha),
+    }
+}
+
+// ## Is SHA
+// Whether the given ref is a full commit SHA already, which doesn't need to
+// be looked up.
+pub fn is_sha(reference: &str) -> bool {
+    reference.len() == 40 && reference.chars().all(|c| c.is_digit(16))
+}
+
+// ## Fetch JSON
+// Most forges answer our requests with a JSON document. This handy function
+// fetches the `url` and parses it.
+pub fn fetch_json(url: &str) -> Result<Json, ResolveError> {
+    let hyper_client: Client = http_client();
+    if let Some(body) = fetch(&hyper_client, url) {
+        Json::from_str(&body).map_err(|_| {
+            warn!("{}: Couldn't parse JSON response: {}", url, &body);
+            ResolveError::BadResponse(String::from("Couldn't parse JSON response"))
+        })
+    } else {
+        Err(ResolveError::NotFound(format!("Couldn't find {}", url)))
+    }
+}
+
+// ## Resolve From JSON
+// Most forges answer our request for a branch with a JSON document
+// containing the SHA somewhere down the given `path`. This handy function
+// fetches the `url` and looks it up there.
+pub fn resolve_from_json(url: &str, path: &[&str]) -> Result<String, ResolveError> {
+    let json = try!(fetch_json(url));
+    if let Some(&Json::String(ref sha)) = json.find_path(path) {
+        Ok(sha.to_owned())
+    } else {
+        // If we couldn't find the SHA, then there is a problem
+        // we need to inform the user about. Usually this means
+        // they did a typo or the content moved.
+        warn!("{}: SHA not found in JSON: {}", url, &json);
+        Err(ResolveError::NotFound(format!("Couldn't find {}", url)))
+    }
+}
+
+// ## Resolve Pull From JSON
+// Look up the SHAs of the base and head of a pull request at the given
+// paths in the JSON document found at `url`.
+pub fn resolve_pull_from_json(url: &str,
+                              base_path: &[&str],
+                              head_path: &[&str])
+                              -> Result<(String, String), ResolveError> {
+    let json = try!(fetch_json(url));
+    match (json.find_path(base_path), json.find_path(head_path)) {
+        (Some(&Json::String(ref base)), Some(&Json::String(ref head))) => {
+            Ok((base.to_owned(), head.to_owned()))
+        }
+        _ => {
+            warn!("{}: SHAs not found in JSON: {}", url, &json);
+            Err(ResolveError::NotFound(format!("Couldn't find {}", url)))
+        }
+    }
+}
+
+// ## Find Forge
+// Look up the forge by its name, e.g. when processing a job from the queue.
+pub fn find_forge(name: &str) -> Option<Arc<Forge>> {
+    match name {
+        "github" => Some(Arc::new(GitHub::from_env()) as Arc<Forge>),
+        "gitlab" => Some(Arc::new(GitLab::from_env()) as Arc<Forge>),
+        "git" => Some(Arc::new(Git) as Arc<Forge>),
+        "crates" => Some(Arc::new(Crates::from_env()) as Arc<Forge>),
+        _ => None,
+    }
+}
+
+// ## Schedule Update
+// Given the forge, project, SHA and optionally the path of the `manifest` to
+// use, this public function will put the checkout of the sources and running
+// of clippy into the job queue. Returns whether it has been queued – it isn't
+// if it is queued or done already.
+pub fn schedule_update(forge: &Forge, project: &str, sha: &str, manifest: Option<&str>) -> bool {
+    let job = Job {
+        forge: forge.name().to_owned(),
+        project: project.to_owned(),
+        sha: sha.to_owned(),
+        manifest: manifest.map(String::from),
+    };
+    enqueue(&setup_redis(), &lint_key(forge, project, sha, manifest), &job)
+}
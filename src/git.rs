@@ -0,0 +1,168 @@
+// Plain git Backend code – for code not hosted on any forge we know
+
+extern crate url;
+
+use std::cmp;
+use std::process::{Command, Output};
+use std::path::Path;
+use std::time::Instant;
+use tempdir::TempDir;
+use url::percent_encoding::lossy_utf8_percent_decode;
+
+use clippy::{Limits, disk_usage};
+use forge::{Checkout, Forge, ResolveError, is_sha};
+use helpers::list_files;
+
+// ## Git
+// The Forge implementation for any git remote. Projects are identified by
+// their url-encoded remote URL, e.g. `https%3A%2F%2Fgit.example.com%2Fcrate.git`,
+// and instead of downloading an archive, we fetch the commit with git itself.
+pub struct Git;
+
+// ## Remote
+// Decode the project into the remote URL. As we are going to hand it to git
+// directly, only allow the network protocols – `file://` or `ext::` remotes
+// must never reach git.
+fn remote(project: &str) -> Result<String, String> {
+    let remote = lossy_utf8_percent_decode(project.as_bytes());
+    if ["https://", "http://", "git://"].iter().any(|scheme| remote.starts_with(scheme)) {
+        Ok(remote)
+    } else {
+        Err(format!("Unsupported git remote: {}", remote))
+    }
+}
+
+// Git gets killed if it takes longer than this many seconds, e.g. because
+// the remote stalls.
+const GIT_TIMEOUT_SECONDS: u64 = 300;
+
+// ## Run Git
+// Execute git with the given arguments in `path`, making sure it never
+// prompts for credentials, as there is nobody to answer. It runs under
+// `timeout`, which kills git – and the helpers it spawned for the transport –
+// after the given number of `seconds`.
+fn run_git(path: &Path, args: &[&str], seconds: u64) -> Result<Output, String> {
+    match Command::new("timeout")
+              .args(&["--signal=KILL", &seconds.to_string(), "git"])
+              .args(args)
+              .env("GIT_TERMINAL_PROMPT", "0")
+              .current_dir(path)
+              .output() {
+        Ok(output) => {
+            if output.status.success() {
+                Ok(output)
+            } else if output.status.code() == Some(124) || output.status.code() == Some(128 + 9) {
+                Err(format!("git {} timed out after {} seconds", args[0], seconds))
+            } else {
+                Err(format!("git {} failed: {}",
+                            args[0],
+                            String::from_utf8_lossy(&output.stderr)))
+            }
+        }
+        Err(error) => Err(format!("Running git failed: {}", error)),
+    }
+}
+
+// ## Time Left
+// The seconds git may still run for before the `deadline` of the job passes –
+// at most `GIT_TIMEOUT_SECONDS`, and at least one, as `timeout` takes zero to
+// mean forever.
+fn time_left(deadline: Instant) -> Result<u64, String> {
+    let now = Instant::now();
+    if now >= deadline {
+        Err(String::from("Ran out of time fetching the sources"))
+    } else {
+        Ok(cmp::max(1, cmp::min(GIT_TIMEOUT_SECONDS, (deadline - now).as_secs())))
+    }
+}
+
+impl Forge for Git {
+    fn name(&self) -> &'static str {
+        "git"
+    }
+
+    // Ask the remote for the branch with `git ls-remote`, which answers with
+    // one `<sha>\t<ref>` line for every match. We ask for the full ref, so
+    // tags or other refs of the same name don't match. If we are given a full
+    // SHA already, there is nothing to look up.
+    // It runs in an empty directory of its own, so no repository the service
+    // happens to be started from – nor its config – gets involved.
+    fn resolve_sha(&self, project: &str, branch: &str) -> Result<String, ResolveError> {
+        if is_sha(branch) {
+            return Ok(branch.to_owned());
+        }
+        let remote = try!(remote(project).map_err(ResolveError::NotFound));
+        let reference = format!("refs/heads/{}", branch);
+        let temp_dir = try!(TempDir::new("clippy_ls_remote").map_err(|error| {
+            ResolveError::BadResponse(format!("Creating a temporary directory failed: {}", error))
+        }));
+        match run_git(temp_dir.path(),
+                      &["ls-remote", "--", &remote, &reference],
+                      GIT_TIMEOUT_SECONDS) {
+            Ok(output) => {
+                let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+                match stdout.lines().next().and_then(|line| line.split('\t').next()) {
+                    Some(sha) if !sha.is_empty() => Ok(sha.to_owned()),
+                    _ => Err(ResolveError::NotFound(format!("Couldn't find {} on {}", branch, remote))),
+                }
+            }
+            Err(error) => Err(ResolveError::NotFound(error)),
+        }
+    }
+
+    // There is no archive to download, it is the remote we fetch from.
+    fn archive_url(&self, project: &str, _sha: &str) -> String {
+        lossy_utf8_percent_decode(project.as_bytes())
+    }
+
+    fn display_url(&self, project: &str, sha: &str) -> String {
+        format!("{}@{}", self.archive_url(project, sha), sha)
+    }
+
+    // Instead of downloading an archive, do a shallow fetch of just that
+    // commit into an empty repository in `temp_dir`. Not every server allows
+    // fetching a commit by SHA, in which case we fall back to fetching all
+    // branches and tags of the remote.
+    // The SHA comes from the URL, so we make sure it is one before handing it
+    // to git – and separate the options from everything else with `--` anyway.
+    // Every step only gets the time left until the `deadline`, and we can
+    // only tell how much space the repository takes once it is there.
+    fn checkout(&self,
+                project: &str,
+                sha: &str,
+                temp_dir: &TempDir,
+                limits: &Limits,
+                deadline: Instant,
+                logger: &Fn(&str))
+                -> Result<Checkout, String> {
+        if !is_sha(sha) {
+            return Err(format!("Not a commit SHA: {}", sha));
+        }
+        let remote = try!(remote(project));
+        let path = temp_dir.path();
+
+        logger(&format!("Fetching {} from {}", sha, remote));
+        try!(run_git(path, &["init", "--quiet"], try!(time_left(deadline))));
+        if let Err(error) = run_git(path,
+                                    &["fetch", "--quiet", "--depth", "1", "--", &remote, sha],
+                                    try!(time_left(deadline))) {
+            logger(&format!("Shallow fetch failed, fetching everything: {}", error));
+            try!(run_git(path,
+                         &["fetch", "--quiet", "--tags", "--", &remote,
+                           "+refs/heads/*:refs/remotes/origin/*"],
+                         try!(time_left(deadline))));
+        }
+        try!(run_git(path, &["checkout", "--quiet", sha, "--"], try!(time_left(deadline))));
+
+        if disk_usage(path) > limits.disk * 1024 * 1024 {
+            return Err(format!("Checkout takes more than the {} MB of disk space allowed",
+                               limits.disk));
+        }
+
+        // The repository is the `temp_dir` itself
+        Ok(Checkout {
+            root: path.to_path_buf(),
+            files: list_files(path),
+        })
+    }
+}
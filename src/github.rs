@@ -1,168 +1,128 @@
 // Github Specific Backend code
-extern crate redis;
-extern crate time;
-extern crate tempdir;
 
-use std::path::Path;
-use std::thread;
-use tempdir::TempDir;
-use time::now_utc;
+extern crate hyper;
+extern crate rustc_serialize;
 
-use std::slice::SliceConcatExt;
-use redis::{Commands, PipelineCommands};
+use std::collections::BTreeMap;
+use std::env;
+use rustc_serialize::json::Json;
+use hyper::header;
 
-use helpers::{setup_redis, log_redis, download_and_unzip};
-use clippy::{ClippyResult, run as run_clippy};
-
-// ## Update For Github
-// Given the user, repo and SHA, this function fetches the
-// git repo and runs clippy in the folder containing the `Cargo.toml` file
-// This is the internal function invoked from `schedule_update` in a seperat
-// background thread. It will return an Error or the ClippyResult once done.
-fn update_for_github<F>(user: &str,
-                        repo: &str,
-                        sha: &str,
-                        logger: F)
-                        -> Result<ClippyResult, String>
-// One interesting feature of this function (and a few others) is the scoped
-// `logger` which is passed around. During execution this function is invoked
-// multiple times to report on the current state of affairs.
-    where F: Fn(&str)
-{
-    // We start by creating a temporary directory for our checkout
-    logger("Creating Temp Directory...");
-
-    if let Ok(temp_dir) = TempDir::new(&format!("github_{0}_{1}_{2}", user, repo, sha)) {
-
-        // Then we need to download the ZIP-Archive for the given user-repo-sha.
-        // Github has a handy URL to do that directly, which we just pass to the
-        // `download_and_unzip` function.
-        let github_url = format!("https://codeload.github.com/{0}/{1}/zip/{2}",
-                                 user,
-                                 repo,
-                                 sha);
+use forge::{Forge, ResolveError, resolve_from_json, resolve_pull_from_json};
+use helpers::http_client;
 
+// ## Github
+// The Forge implementation for projects hosted on github.com.
+pub struct GitHub {
+    // e.g. `https://api.github.com`
+    api_url: String,
+    // to post commit statuses with, if configured
+    token: Option<String>,
+}
 
-        logger(&format!("Fetching {}", &github_url));
-        match download_and_unzip(&github_url, &temp_dir) {
-            Ok(files) => {
-                // Once unzipped, we report back the files found and try to find the
-                // patch containing the 'cargo.toml' file – this iter stops at the first
-                // item found.
-                logger(&format!("Extracted: \n - {}", files.join("\n - ")));
-                match files.iter().find(|item| item.to_lowercase().ends_with("cargo.toml")) {
-                    Some(file) => {
-                        // Look up the bounding directory for that file, report
-                        // that path and execute `run_clippy` in that folder
-                        let path = Path::new(file);
-                        let parent_directory = path.parent().unwrap();
-                        logger(&format!("Cargo file found in {}",
-                                        parent_directory.to_string_lossy().into_owned()));
-                        logger("-------------------------------- Running Clippy");
-                        run_clippy(parent_directory, logger)
-                    }
-                    // Report back if there is no `Cargo.toml` file or if there has been
-                    // any other error during download_and_unzip.
-                    _ => Err(String::from("No `Cargo.toml` file found in archive.")),
+impl GitHub {
+    // Create the Github forge talking to the API configured in the
+    // `GITHUB_API_URL` environment variable – e.g. a local fake for testing –
+    // falling back to api.github.com if not found. Commit statuses are only
+    // posted if a token is configured in `GITHUB_TOKEN`.
+    pub fn from_env() -> GitHub {
+        GitHub {
+            api_url: env::var("GITHUB_API_URL")
+                         .unwrap_or("https://api.github.com".to_owned())
+                         .trim_right_matches('/')
+                         .to_owned(),
+            token: env::var("GITHUB_TOKEN").ok().and_then(|token| {
+                if token.is_empty() {
+                    None
+                } else {
+                    Some(token)
                 }
-            }
-            Err(err) => Err(err),
+            }),
         }
-    } else {
-        // We could run into some IO error, causing the temporary directory creation to
-        // fail. Report that appropriately.
-        Err(String::from("Creating temp directory failed"))
     }
 }
 
-// ## Schedule Update
-// Given the username, repo and SHA from Github, this public function
-// will schedule the fetching and running of clippy in a background thread.
-pub fn schedule_update(user: &str, repo: &str, sha: &str) {
+impl Forge for GitHub {
+    fn name(&self) -> &'static str {
+        "github"
+    }
 
-    // Setup the scope for the background thread. We need to move all
-    // variables here to ensure they can't change during thread runtime.
-    let user = user.to_owned();
-    let repo = repo.to_owned();
-    let sha = sha.to_owned();
-    let base_key = format!("github/{0}/{1}:{2}", user, repo, sha).to_owned();
+    // Github tells us about the SHA of the branch through its git
+    // references API.
+    fn resolve_sha(&self, project: &str, branch: &str) -> Result<String, ResolveError> {
+        resolve_from_json(&format!("{0}/repos/{1}/git/refs/heads/{2}",
+                                   self.api_url,
+                                   project,
+                                   branch),
+                          &["object", "sha"])
+    }
 
-    let result_key = format!("result/{}", base_key).to_owned();
-    let log_key = format!("log/{}", base_key).to_owned();
+    // The head of a pull request is what `refs/pull/:number/head` points
+    // to, which Github offers the archive of like for any other commit.
+    fn resolve_pull(&self, project: &str, number: &str) -> Result<(String, String), ResolveError> {
+        resolve_pull_from_json(&format!("{0}/repos/{1}/pulls/{2}", self.api_url, project, number),
+                               &["base", "sha"],
+                               &["head", "sha"])
+    }
 
-    // now spawn the background thread. We create both the redis connection
-    // and the logger clojure in here to avoid ownership problems.
-    thread::spawn(move || {
-        let redis: redis::Connection = setup_redis();
-        let logger = |statement: &str| log_redis(&redis, &log_key, statement);
+    // Github has a handy URL to download the ZIP-Archive for the given
+    // user-repo-sha directly.
+    fn archive_url(&self, project: &str, sha: &str) -> String {
+        format!("https://codeload.github.com/{0}/zip/{1}", project, sha)
+    }
 
-        // But before starting exection, make sure the redis connection is up
-        // and the logs aren't yet written by anyone. We do that inside a redis
-        // transaction, ensuring we are atomic and would.
-        // For that we pass into the transaction the keys we want to be informed
-        // about if changes happen to them while we execute. In that case our
-        // transaction was cancelled before it got executed and we knew that another
-        // background thread was already working on it.
-        if let Ok(existing) = redis::transaction(&redis,
-                                                 &[log_key.clone(), result_key.clone()],
-                                                 |pipe| {
-                                                     match redis.exists(result_key.clone()) {
-                                                         Ok(Some(false)) => {
-                                                             pipe.cmd("RPUSH")
-                                                                 .arg(log_key.clone())
-                                                                 .arg(format!("{0} started \
-                                                                               processing \
-                                                                               github/{1}/{2}:\
-                                                                               {3}",
-                                                                              now_utc().rfc3339(),
-                                                                              user,
-                                                                              repo,
-                                                                              sha))
-                                                                 .ignore()
-                                                                 .execute(&redis);
-                                                             Ok(Some(false))
-                                                         }
-                                                         _ => Ok(Some(true)),
-                                                     }
-                                                 }) {
+    fn display_url(&self, project: &str, sha: &str) -> String {
+        format!("https://github.com/{0}/tree/{1}", project, sha)
+    }
 
-             // we have been alerted, the key already existed
-             // so someone else is writing a log file. We should stop now.
-            if existing {
-                return;
-            }
-        }
+    fn file_url(&self, project: &str, sha: &str, file: &str, line: u64) -> Option<String> {
+        Some(format!("https://github.com/{0}/blob/{1}/{2}#L{3}", project, sha, file, line))
+    }
 
-        // No background thread yet, we are ready to roll: execute `update_for_github`
-        // and parse the result. If there is ClippyResult, match it to the appropriate
-        // status output, otherwise, report the error and set the status to "failed".
-        let text: String = match update_for_github(&user, &repo, &sha, logger) {
-            Ok(result) => {
-                match result {
-                    ClippyResult::Success => String::from("success"),
-                    ClippyResult::WithWarnings(warnings) => format!("{0} warnings", warnings),
-                    ClippyResult::WithErrors(errors, _) => format!("{0} errors", errors)
-                }
-            }
-            Err(error) => {
-                log_redis(&redis, &log_key, &format!("Failed: {}", error));
-                String::from("failed")
-            }
+    // Post the status as a commit status, if we have a token to do so. While
+    // linting, the status is pending. A clean result is a success, warnings
+    // and errors are a failure, while a run that failed or timed out is an
+    // error.
+    fn report_status(&self,
+                     project: &str,
+                     sha: &str,
+                     context: &str,
+                     status: &str,
+                     target_url: Option<&str>)
+                     -> Result<(), String> {
+        let token = match self.token {
+            Some(ref token) => token,
+            None => return Ok(()),
+        };
+        let state = match status {
+            "linting" | "retrying" => "pending",
+            "success" => "success",
+            "failed" | "timeout" => "error",
+            _ => "failure",
         };
 
-        // log the output from clippy and set the result into the redis cache.
-        // we are done with our background thread. Rust will take care of cleaning
-        // up for us here automatically.
-        log_redis(&redis,
-                  &log_key,
-                  &format!("------------------------------------------\n Clippy's final \
-                            verdict: {}",
-                           text));
-        redis::pipe()
-            .cmd("SET")
-            .arg(result_key)
-            .arg(text.clone())
-            .ignore()
-            .execute(&redis);
-    });
+        let mut body: BTreeMap<String, Json> = BTreeMap::new();
+        body.insert("state".to_owned(), Json::String(state.to_owned()));
+        body.insert("description".to_owned(), Json::String(status.to_owned()));
+        body.insert("context".to_owned(), Json::String(context.to_owned()));
+        if let Some(target_url) = target_url {
+            body.insert("target_url".to_owned(), Json::String(target_url.to_owned()));
+        }
+        let body = Json::Object(body).to_string();
+
+        let url = format!("{0}/repos/{1}/statuses/{2}", self.api_url, project, sha);
+        let client = http_client();
+        let res = client.post(&url)
+                        .header(header::UserAgent("Clippy/1.0".to_owned()))
+                        .header(header::Authorization(format!("token {}", token)))
+                        .header(header::ContentType(mime!(Application/Json)))
+                        .header(header::Connection::close())
+                        .body(body.as_str())
+                        .send();
+        match res {
+            Ok(ref res) if res.status.is_success() => Ok(()),
+            Ok(res) => Err(format!("{} answered {}", url, res.status)),
+            Err(error) => Err(format!("Couldn't connect to {}: {}", url, error)),
+        }
+    }
 }
@@ -0,0 +1,81 @@
+// Gitlab Specific Backend code
+
+use std::env;
+
+use forge::{Forge, ResolveError, resolve_from_json, resolve_pull_from_json};
+
+// ## Gitlab
+// The Forge implementation for projects hosted on a Gitlab instance.
+pub struct GitLab {
+    // e.g. `https://gitlab.com`
+    base_url: String,
+}
+
+impl GitLab {
+    // Create the Gitlab forge for the instance configured in the `GITLAB_URL`
+    // environment variable, so a self-hosted instance can be used, falling
+    // back to gitlab.com if not found.
+    pub fn from_env() -> GitLab {
+        GitLab {
+            base_url: env::var("GITLAB_URL")
+                          .unwrap_or("https://gitlab.com".to_owned())
+                          .trim_right_matches('/')
+                          .to_owned(),
+        }
+    }
+
+    // Gitlab identifies projects in its API by their url-encoded path,
+    // so `group/project` becomes `group%2Fproject`.
+    fn api_url(&self, project: &str) -> String {
+        format!("{0}/api/v4/projects/{1}", self.base_url, encode(project))
+    }
+}
+
+// ## Encode
+// Percent-encode everything but the unreserved characters, so a path – or a
+// branch like `feature/x` – becomes a single segment of an API URL.
+fn encode(segment: &str) -> String {
+    segment.bytes()
+           .map(|byte| {
+               match byte {
+                   b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' => {
+                       (byte as char).to_string()
+                   }
+                   _ => format!("%{:02X}", byte),
+               }
+           })
+           .collect()
+}
+
+impl Forge for GitLab {
+    fn name(&self) -> &'static str {
+        "gitlab"
+    }
+
+    fn resolve_sha(&self, project: &str, branch: &str) -> Result<String, ResolveError> {
+        resolve_from_json(&format!("{0}/repository/branches/{1}",
+                                   self.api_url(project),
+                                   encode(branch)),
+                          &["commit", "id"])
+    }
+
+    // Gitlab calls them merge requests, and tells us about their SHAs.
+    fn resolve_pull(&self, project: &str, number: &str) -> Result<(String, String), ResolveError> {
+        resolve_pull_from_json(&format!("{0}/merge_requests/{1}", self.api_url(project), number),
+                               &["diff_refs", "base_sha"],
+                               &["sha"])
+    }
+
+    // Gitlab offers the ZIP-Archive for any SHA through its API.
+    fn archive_url(&self, project: &str, sha: &str) -> String {
+        format!("{0}/repository/archive.zip?sha={1}", self.api_url(project), sha)
+    }
+
+    fn display_url(&self, project: &str, sha: &str) -> String {
+        format!("{0}/{1}/tree/{2}", self.base_url, project, sha)
+    }
+
+    fn file_url(&self, project: &str, sha: &str, file: &str, line: u64) -> Option<String> {
+        Some(format!("{0}/{1}/blob/{2}/{3}#L{4}", self.base_url, project, sha, file, line))
+    }
+}
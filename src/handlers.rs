@@ -8,214 +8,811 @@ extern crate url;
 extern crate router;
 
 use std::vec::Vec;
-use rustc_serialize::json::Json;
+use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use rustc_serialize::json::{self, Json};
 
 use iron::modifiers::Redirect;
 use iron::prelude::*;
 use iron::status;
+use iron::Handler;
 use iron::Url as iUrl;
-
-use hyper::client::Client;
+use iron::headers::{CacheControl, CacheDirective};
+use iron::mime::Mime;
+use url::form_urlencoded;
 
 use router::Router;
 
 use std::slice::SliceConcatExt;
 use redis::{Commands, Value};
 
-use helpers::{setup_redis, fetch, get_status_or,  local_redir, set_redis_cache};
-use github::schedule_update as schedule_github_update;
+use helpers::{setup_redis, get_status_or, local_redir, set_redis_cache, query_param,
+              load_diagnostics, authorized, rate_limit, rate_limited, status_color};
+use clippy::{Diagnostic, count_by_lint};
+use diff::{fixed_diagnostics, new_diagnostics, new_status};
+use history;
+use sarif::to_sarif;
+use reports;
+use html;
+use stream::EventStream;
+use badge::{Style, color_code, render as render_badge};
+use jobs::{is_busy, is_retrying, queue_depth, reset, running_workers};
+use forge::{Forge, ResolveError, base_key, is_sha, lint_key, schedule_update};
+use workspace::is_valid_manifest_path;
 
-// The base URL for our badges. We aren't actually compiling them ourselfes,
-// but are reusing the great shields.io service.
+// The base URL for badges, if we aren't rendering them ourselves but are
+// reusing the great shields.io service.
 static BADGE_URL_BASE: &'static str = "https://img.shields.io/badge/";
 
 
-// Github Finder
-// Expand a branch name into the hash, cache the redirect for 5min
-// `/github/:user/:repo/badge.svg => /github/:user/:repo/:sha/badge.svg`
-pub fn github_finder(req: &mut Request) -> IronResult<Response> {
+// ## Finder
+// Expand a branch name into the hash on the forge, cache the redirect for 5min
+// `/github/:user/:repo/badge.svg => /github/sha/:user/:repo/:sha/badge.svg`
+// Except for the history of the branch, `history.json` and `history.svg`,
+// which is about the branch rather than a single SHA.
+pub struct Finder {
+    forge: Arc<Forge>,
+}
 
-    // Learn the parameters given to the request
-    let router = req.extensions.get::<Router>().unwrap();
-    let redis: redis::Connection = setup_redis();
-    let hyper_client: Client = Client::new();
+impl Finder {
+    pub fn new(forge: Arc<Forge>) -> Finder {
+        Finder { forge: forge }
+    }
+}
+
+impl Handler for Finder {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+
+        // Learn the parameters given to the request
+        let manifest = match manifest_param(req) {
+            Ok(manifest) => manifest,
+            Err(response) => return Ok(response),
+        };
+        let router = req.extensions.get::<Router>().unwrap();
+        let redis: redis::Connection = setup_redis();
 
-    let user = router.find("user").unwrap();
-    let repo = router.find("repo").unwrap();
-    let branch = router.find("branch").unwrap_or("master");
-    let method = router.find("method").unwrap_or("badge.svg");
+        let project = find_project(router);
+        let branch = router.find("branch").unwrap_or(self.forge.default_branch());
 
-    // And the cache key we use to keep the map from branch->SHA
-    let redis_key = format!("cached-sha/github/{0}/{1}:{2}", user, repo, branch);
+        let history_format = match router.find("method") {
+            Some("history.json") => Some("json"),
+            Some("history.svg") => Some("svg"),
+            _ => None,
+        };
+        if let Some(format) = history_format {
+            return history_response(&*self.forge,
+                                     &redis,
+                                     &project,
+                                     branch,
+                                     manifest.as_ref().map(|m| m.as_str()),
+                                     router.find("member"),
+                                     format);
+        }
+        // If a single member of a workspace is asked for, we keep it in the
+        // path we redirect to.
+        let method = match router.find("member") {
+            Some(member) => format!("{0}/{1}", member, router.find("method").unwrap_or("badge.svg")),
+            None => router.find("method").unwrap_or("badge.svg").to_owned(),
+        };
 
-    // Let's see if redis has this key. If it does, redirect the request
-    // directly
+        // Look up the SHA – from the cache, if we can – and redirect there
+        match resolve_cached(&*self.forge, &redis, &project, branch) {
+            Ok(sha) => {
+                local_redir(&format!("/{0}/sha/{1}/{2}/{3}",
+                                     self.forge.name(),
+                                     project,
+                                     sha,
+                                     method),
+                            &req.url)
+            }
+            // Usually this means they did a typo or the content moved
+            // – either way, we fire a 404 – Not Found.
+            Err(ResolveError::NotFound(msg)) => {
+                Ok(Response::with((status::NotFound, msg)))
+            }
+            Err(ResolveError::BadResponse(msg)) => {
+                Ok(Response::with((status::InternalServerError, msg)))
+            }
+        }
+    }
+}
+
+// ## History Response
+// The results for the SHAs the branch pointed to over time, as JSON or a
+// sparkline. To keep it current, we look up where the branch points now and
+// lint that if we haven't yet.
+fn history_response(forge: &Forge,
+                    redis: &redis::Connection,
+                    project: &str,
+                    branch: &str,
+                    manifest: Option<&str>,
+                    member: Option<&str>,
+                    format: &str)
+                    -> IronResult<Response> {
+    if let Ok(sha) = resolve_cached(forge, redis, project, branch) {
+        get_status_or(redis.get(format!("result/{}", lint_key(forge, project, &sha, manifest))),
+                      || { schedule_update(forge, project, &sha, manifest); });
+    }
+
+    let points = history::load(redis, forge, project, branch, manifest, member);
+    let mut resp = match format {
+        "svg" => {
+            Response::with((status::Ok,
+                            "image/svg+xml".parse::<Mime>().unwrap(),
+                            history::sparkline(&points)))
+        }
+        _ => Response::with((status::Ok, mime!(Application/Json), history::to_json(&points).to_string())),
+    };
+    resp.headers.set(CacheControl(vec![CacheDirective::NoCache, CacheDirective::MaxAge(0)]));
+    Ok(resp)
+}
+
+// ## Resolve Cached
+// Expand the branch name into the SHA on the forge, keeping the map from
+// branch->SHA in the cache for 5min. Full SHAs are taken as they are.
+fn resolve_cached(forge: &Forge,
+                  redis: &redis::Connection,
+                  project: &str,
+                  branch: &str)
+                  -> Result<String, ResolveError> {
+    if is_sha(branch) {
+        return Ok(branch.to_owned());
+    }
+
+    // The cache key we use to keep the map from branch->SHA
+    let redis_key = format!("cached-sha/{}", base_key(forge, project, branch));
+
+    // Let's see if redis has this key. If it does, we are done
     match redis.get(redis_key.to_owned()) {
-        Ok(Value::Data(sha)) => {
-            local_redir(&format!("/github/sha/{0}/{1}/{2}/{3}",
-                                 user,
-                                 repo,
-                                 String::from_utf8(sha).unwrap(),
-                                 method),
-                        &req.url)
-        }
-        // otherwise, we need to look up the current SHA for the branch
+        Ok(Value::Data(sha)) => Ok(String::from_utf8(sha).unwrap()),
+        // otherwise, we need to ask the forge for the current SHA of the branch
+        // and – once found – store the SHA in the cache and the history
         _ => {
-            let github_url = format!("https://api.github.com/repos/{0}/{1}/git/refs/heads/{2}",
-                                     user,
-                                     repo,
-                                     branch);
-            // Fetch the content API request for the Github URL,
-            // Parse its JSON and try to find the `SHA`-key.
-            if let Some(body) = fetch(&hyper_client, &github_url) {
-                if let Ok(json) = Json::from_str(&body) {
-                    if let Some(&Json::String(ref sha)) = json.find_path(&["object", "sha"]) {
-                        // Once found, store the SHA in the cache and redirect
-                        // the request to
-                        set_redis_cache(&redis, &redis_key, &sha);
-                        local_redir(&format!("/github/sha/{0}/{1}/{2}/{3}",
-                                             user,
-                                             repo,
-                                             sha,
-                                             method),
-                                    &req.url)
-                    } else {
-                        // If we couldn't find the SHA, then there is a problem
-                        // we need to inform the user about. Usually this means
-                        // they did a typo or the content moved – either way, we
-                        // fire a 404 – Not Found.
-                        warn!("{}: SHA not found in JSON: {}", &github_url, &json);
-                        Ok(Response::with((status::NotFound,
-                                           format!("Couldn't find on Github {}", &github_url))))
+            let sha = try!(forge.resolve_sha(project, branch));
+            set_redis_cache(redis, &redis_key, &sha);
+            history::record(redis, forge, project, branch, &sha);
+            Ok(sha)
+        }
+    }
+}
+
+// ## Sha Handler
+// Handle the request for a status report of a project-sha combination.
+// Usually the request ends up here after having been redirected via the
+// `Finder`-handler.
+pub struct ShaHandler {
+    forge: Arc<Forge>,
+}
+
+impl ShaHandler {
+    pub fn new(forge: Arc<Forge>) -> ShaHandler {
+        ShaHandler { forge: forge }
+    }
+}
+
+impl Handler for ShaHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+
+        // First extract all the request information, starting with the path
+        // to the `Cargo.toml` to use, if given as the `manifest`-parameter
+        let manifest = match manifest_param(req) {
+            Ok(manifest) => manifest,
+            Err(response) => return Ok(response),
+        };
+        let manifest = manifest.as_ref().map(|m| m.as_str());
+
+        let router = req.extensions.get::<Router>().unwrap();
+
+        let project = find_project(router);
+        let sha = router.find("sha").unwrap();
+        let member = router.find("member");
+        let method = router.find("method").unwrap_or("badge.svg");
+        let key = lint_key(&*self.forge, &project, sha, manifest);
+
+        // Once the whole project has been linted, every member has its result
+        // stored. If the member asked for has none, it doesn't exist.
+        let redis: redis::Connection = setup_redis();
+        let linted = redis.exists(format!("result/{}", key)).unwrap_or(false);
+        if let Some(member) = member {
+            if linted && !redis.exists(format!("result/{}/{}", key, member)).unwrap_or(false) {
+                return Ok(Response::with((status::NotFound,
+                                          format!("No member {} found in {}", member, key))));
+            }
+        }
+
+        // Before a job gets queued, make sure there is something to lint,
+        // rather than storing a `failed` for a typo forever.
+        if !linted && !is_busy(&redis, &key) {
+            match self.forge.check_sha(&project, sha) {
+                Ok(()) => {}
+                Err(ResolveError::NotFound(msg)) => {
+                    return Ok(Response::with((status::NotFound, msg)));
+                }
+                Err(ResolveError::BadResponse(msg)) => {
+                    return Ok(Response::with((status::InternalServerError, msg)));
+                }
+            }
+        }
+
+        // and hand over to the shared `status_response`, which triggers
+        // a `schedule_update` if there is no result yet
+        status_response(&req.url,
+                        &*self.forge,
+                        &project,
+                        &key,
+                        sha,
+                        member,
+                        method,
+                        || { schedule_update(&*self.forge, &project, sha, manifest); })
+    }
+}
+
+// ## Pull Handler
+// Lint a pull request – or merge request – reporting only the warnings and
+// errors it introduces, not those found in its base already. To do so, both
+// the head and the base SHA are linted, like any other SHA, and their
+// diagnostics compared. Besides the usual `status` and badges, `new.json`
+// lists the new diagnostics and `log` describes them for humans.
+pub struct PullHandler {
+    forge: Arc<Forge>,
+}
+
+impl PullHandler {
+    pub fn new(forge: Arc<Forge>) -> PullHandler {
+        PullHandler { forge: forge }
+    }
+}
+
+impl Handler for PullHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let manifest = match manifest_param(req) {
+            Ok(manifest) => manifest,
+            Err(response) => return Ok(response),
+        };
+        let manifest = manifest.as_ref().map(|m| m.as_str());
+
+        let router = req.extensions.get::<Router>().unwrap();
+        let redis: redis::Connection = setup_redis();
+
+        let project = find_project(router);
+        let number = router.find("number").unwrap();
+        let method = router.find("method").unwrap_or("badge.svg");
+
+        // Pull requests move on with every push, so we only keep their SHAs
+        // around for 5min, like those of branches.
+        let redis_key = format!("cached-pull/{}/{}/{}", self.forge.name(), project, number);
+        let cached: Option<String> = redis.get(redis_key.to_owned()).unwrap_or(None);
+        let shas: Option<(String, String)> = cached.and_then(|cached| {
+            let shas: Vec<&str> = cached.split("...").collect();
+            match shas.len() {
+                2 => Some((shas[0].to_owned(), shas[1].to_owned())),
+                _ => None,
+            }
+        });
+        let (base, head) = match shas {
+            Some(shas) => shas,
+            None => {
+                match self.forge.resolve_pull(&project, number) {
+                    Ok((base, head)) => {
+                        set_redis_cache(&redis, &redis_key, &format!("{}...{}", base, head));
+                        (base, head)
+                    }
+                    Err(ResolveError::NotFound(msg)) => {
+                        return Ok(Response::with((status::NotFound, msg)));
+                    }
+                    Err(ResolveError::BadResponse(msg)) => {
+                        return Ok(Response::with((status::InternalServerError, msg)));
+                    }
+                }
+            }
+        };
+
+        let filename: Vec<&str> = method.rsplitn(2, '.').collect();
+        let (method, ext) = match filename.len() {
+            2 => (filename[1], filename[0]),
+            _ => (filename[0], ""),
+        };
+
+        let base_key = lint_key(&*self.forge, &project, &base, manifest);
+        let head_key = lint_key(&*self.forge, &project, &head, manifest);
+        let compared = load_both(&redis,
+                                 &base_key,
+                                 &head_key,
+                                 || { schedule_update(&*self.forge, &project, &base, manifest); },
+                                 || { schedule_update(&*self.forge, &project, &head, manifest); });
+        let new = compared.as_ref().ok().map(|&(ref base, ref head)| new_diagnostics(base, head));
+        let (text, color) = match (&compared, &new) {
+            (&Err((ref text, ref color)), _) => (text.clone(), color.clone()),
+            (_, &Some(ref new)) => {
+                let text = new_status(new);
+                let color = status_color(&text);
+                (text, color)
+            }
+            _ => unreachable!(),
+        };
+        // If either job has been abandoned before, let the user know we are
+        // retrying it.
+        let retrying = is_retrying(&redis, &base_key) || is_retrying(&redis, &head_key);
+        let (text, color) = if text == "linting" && retrying {
+            (String::from("retrying"), String::from("orange"))
+        } else {
+            (text, color)
+        };
+
+        match (method, new) {
+            ("status", _) => Ok(Response::with((status::Ok, text))),
+            // The badge of a pull request shows whatever its head is right
+            // now, which changes with every push, so it is always temporary.
+            ("badge", _) | ("emojibadge", _) | ("fullemojibadge", _) => {
+                status_badge(&req.url, method, ext, &text, &color, true)
+            }
+            ("new", Some(new)) => {
+                let mut result: BTreeMap<String, Json> = BTreeMap::new();
+                result.insert("base".to_owned(), Json::String(base));
+                result.insert("head".to_owned(), Json::String(head));
+                result.insert("status".to_owned(), Json::String(text));
+                result.insert("new".to_owned(),
+                              Json::from_str(&json::encode(&new).unwrap()).unwrap());
+                Ok(Response::with((status::Ok, mime!(Application/Json), Json::Object(result).to_string())))
+            }
+            ("log", Some(new)) => {
+                let mut lines = vec![format!("Comparing {} (head) to {} (base): {}", head, base, text)];
+                lines.extend(new.iter().map(describe));
+                Ok(Response::with((status::Ok, lines.join("\n"))))
+            }
+            ("new", None) | ("log", None) => {
+                Ok(Response::with((status::Accepted, format!("Not done yet: {}", text))))
+            }
+            _ => Ok(Response::with((status::BadRequest, format!("{} Not Implemented.", method)))),
+        }
+    }
+}
+
+// ## Load Both
+// The diagnostics of both the `base` and the `head` result. If we don't have
+// both yet, we return the status to show instead: that we are linting them –
+// calling the `trigger` of those missing – or that either run failed.
+fn load_both<B, H>(redis: &redis::Connection,
+                   base: &str,
+                   head: &str,
+                   trigger_base: B,
+                   trigger_head: H)
+                   -> Result<(Vec<Diagnostic>, Vec<Diagnostic>), (String, String)>
+    where B: Fn(),
+          H: Fn()
+{
+    let statuses = [get_status_or(redis.get(format!("result/{}", head)), trigger_head),
+                    get_status_or(redis.get(format!("result/{}", base)), trigger_base)];
+    for &(ref text, ref color) in statuses.iter() {
+        if text == "linting" || text == "failed" || text == "timeout" {
+            return Err((text.clone(), color.clone()));
+        }
+    }
+    match (load_diagnostics(redis, &format!("diagnostics/{}", base)),
+           load_diagnostics(redis, &format!("diagnostics/{}", head))) {
+        (Some(base), Some(head)) => Ok((base, head)),
+        _ => Err((String::from("failed"), status_color("failed"))),
+    }
+}
+
+// ## Describe
+// A line about the `diagnostic` for humans, e.g.
+// `warning[needless_return]: unneeded return statement at src/lib.rs:12`
+fn describe(diagnostic: &Diagnostic) -> String {
+    let code = diagnostic.code.as_ref().map_or(String::new(), |code| format!("[{}]", code));
+    match diagnostic.span {
+        Some(ref span) => {
+            format!("{}{}: {} at {}:{}",
+                    diagnostic.level,
+                    code,
+                    diagnostic.message,
+                    span.file,
+                    span.line_start)
+        }
+        None => format!("{}{}: {}", diagnostic.level, code, diagnostic.message),
+    }
+}
+
+// ## Compare
+// Compare the diagnostics of two SHAs – or branches – given as
+// `:base...:head`: which were fixed, which appeared and the net change of
+// every lint, as JSON.
+pub struct Compare {
+    forge: Arc<Forge>,
+}
+
+impl Compare {
+    pub fn new(forge: Arc<Forge>) -> Compare {
+        Compare { forge: forge }
+    }
+}
+
+impl Handler for Compare {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let manifest = match manifest_param(req) {
+            Ok(manifest) => manifest,
+            Err(response) => return Ok(response),
+        };
+        let manifest = manifest.as_ref().map(|m| m.as_str());
+
+        let router = req.extensions.get::<Router>().unwrap();
+        let redis: redis::Connection = setup_redis();
+
+        let project = find_project(router);
+        let range: Vec<&str> = router.find("range").unwrap_or("").splitn(2, "...").collect();
+        if range.len() != 2 {
+            return Ok(Response::with((status::BadRequest, "Expected :base...:head")));
+        }
+
+        let mut shas: Vec<String> = Vec::new();
+        for branch in range {
+            match resolve_cached(&*self.forge, &redis, &project, branch) {
+                Ok(sha) => shas.push(sha),
+                Err(ResolveError::NotFound(msg)) => {
+                    return Ok(Response::with((status::NotFound, msg)));
+                }
+                Err(ResolveError::BadResponse(msg)) => {
+                    return Ok(Response::with((status::InternalServerError, msg)));
+                }
+            }
+        }
+        let (base, head) = (&shas[0], &shas[1]);
+
+        let mut result: BTreeMap<String, Json> = BTreeMap::new();
+        result.insert("base".to_owned(), Json::String(base.to_owned()));
+        result.insert("head".to_owned(), Json::String(head.to_owned()));
+
+        match load_both(&redis,
+                        &lint_key(&*self.forge, &project, base, manifest),
+                        &lint_key(&*self.forge, &project, head, manifest),
+                        || { schedule_update(&*self.forge, &project, base, manifest); },
+                        || { schedule_update(&*self.forge, &project, head, manifest); }) {
+            Ok((base_diagnostics, head_diagnostics)) => {
+                let fixed = fixed_diagnostics(&base_diagnostics, &head_diagnostics);
+                let new = new_diagnostics(&base_diagnostics, &head_diagnostics);
+
+                // The net change of every lint found in either of them
+                let base_counts = count_by_lint(&base_diagnostics);
+                let head_counts = count_by_lint(&head_diagnostics);
+                let mut delta: BTreeMap<String, Json> = BTreeMap::new();
+                for lint in base_counts.keys().chain(head_counts.keys()) {
+                    let change = *head_counts.get(lint).unwrap_or(&0) as i64 -
+                                 *base_counts.get(lint).unwrap_or(&0) as i64;
+                    if change != 0 {
+                        delta.insert(lint.to_owned(), Json::I64(change));
                     }
+                }
+
+                result.insert("status".to_owned(), Json::String(new_status(&new)));
+                result.insert("fixed".to_owned(),
+                              Json::from_str(&json::encode(&fixed).unwrap()).unwrap());
+                result.insert("new".to_owned(),
+                              Json::from_str(&json::encode(&new).unwrap()).unwrap());
+                result.insert("delta".to_owned(), Json::Object(delta));
+                Ok(Response::with((status::Ok, mime!(Application/Json), Json::Object(result).to_string())))
+            }
+            // We don't have both results yet, let them know why
+            Err((text, _)) => {
+                result.insert("status".to_owned(), Json::String(text));
+                Ok(Response::with((status::Accepted, mime!(Application/Json), Json::Object(result).to_string())))
+            }
+        }
+    }
+}
+
+// ## Relint
+// Force a fresh run for a SHA – or the current head of a branch – e.g. after
+// clippy has been upgraded or the last run failed for flaky reasons. As this
+// throws away the results we have, the request must be authorized with the
+// token configured in `RELINT_TOKEN`, and every project can only be relinted
+// once every `RELINT_INTERVAL` seconds (5 minutes by default).
+pub struct Relint {
+    forge: Arc<Forge>,
+}
+
+impl Relint {
+    pub fn new(forge: Arc<Forge>) -> Relint {
+        Relint { forge: forge }
+    }
+}
+
+impl Handler for Relint {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        if !authorized(req, "RELINT_TOKEN") {
+            return Ok(Response::with((status::Unauthorized, "Missing or invalid token")));
+        }
+
+        let manifest = match manifest_param(req) {
+            Ok(manifest) => manifest,
+            Err(response) => return Ok(response),
+        };
+        let manifest = manifest.as_ref().map(|m| m.as_str());
+
+        let router = req.extensions.get::<Router>().unwrap();
+        let redis: redis::Connection = setup_redis();
+        let project = find_project(router);
+
+        // A branch is looked up right away – not from the cache, as it is
+        // likely the branch moved on, which is why we are asked to relint.
+        let sha = match router.find("sha") {
+            Some(sha) => sha.to_owned(),
+            None => {
+                let branch = router.find("branch").unwrap_or(self.forge.default_branch());
+                if is_sha(branch) {
+                    branch.to_owned()
                 } else {
-                    warn!("{}: Couldn't parse Githubs JSON response: {}",
-                          &github_url,
-                          &body);
-                    Ok(Response::with((status::InternalServerError,
-                                       "Couldn't parse Githubs JSON response")))
+                    match self.forge.resolve_sha(&project, branch) {
+                        Ok(sha) => {
+                            set_redis_cache(&redis,
+                                            &format!("cached-sha/{}",
+                                                     base_key(&*self.forge, &project, branch)),
+                                            &sha);
+                            history::record(&redis, &*self.forge, &project, branch, &sha);
+                            sha
+                        }
+                        Err(ResolveError::NotFound(msg)) => {
+                            return Ok(Response::with((status::NotFound, msg)));
+                        }
+                        Err(ResolveError::BadResponse(msg)) => {
+                            return Ok(Response::with((status::InternalServerError, msg)));
+                        }
+                    }
                 }
-            } else {
-                Ok(Response::with((status::NotFound,
-                                   format!("Couldn't find on Github {}", &github_url))))
             }
+        };
+
+        let key = lint_key(&*self.forge, &project, &sha, manifest);
+        if is_busy(&redis, &key) {
+            return Ok(Response::with((status::Conflict,
+                                      format!("{} is being linted already", key))));
+        }
+
+        let interval = env::var("RELINT_INTERVAL")
+                           .ok()
+                           .and_then(|interval| interval.parse().ok())
+                           .unwrap_or(5 * 60);
+        let limit_key = format!("relint-limit/{}/{}", self.forge.name(), project);
+        if let Some(wait) = rate_limited(&redis, &limit_key) {
+            let mut resp = Response::with((status::TooManyRequests,
+                                           format!("{} was relinted recently, try again in {} seconds",
+                                                   project,
+                                                   wait)));
+            resp.headers.set_raw("Retry-After", vec![wait.to_string().into_bytes()]);
+            return Ok(resp);
         }
+
+        // Only a relint we are actually doing counts against the limit – if
+        // another request got there first, it is theirs.
+        if !reset(&redis, &key) || !schedule_update(&*self.forge, &project, &sha, manifest) {
+            return Ok(Response::with((status::Conflict,
+                                      format!("{} is being linted already", key))));
+        }
+        rate_limit(&redis, &limit_key, interval);
+        Ok(Response::with((status::Accepted, format!("Relinting {}", key))))
     }
 }
 
-// ## Github Handler
-// Handle the request for a status report of a user-repo-sha combination.
-// Usually the request ends up here after having been redirected via the
-// `github_finder`-handler.
-// In this request is where the actual sausage is done.
-pub fn github_handler(req: &mut Request) -> IronResult<Response> {
+// ## Manifest Param
+// The path to the `Cargo.toml` to use, if given as the `manifest`-parameter,
+// or the `BadRequest` response to send if it isn't a valid one.
+fn manifest_param(req: &mut Request) -> Result<Option<String>, Response> {
+    let manifest = query_param(req, "manifest");
+    if let Some(ref manifest) = manifest {
+        if !is_valid_manifest_path(manifest) {
+            return Err(Response::with((status::BadRequest,
+                                       format!("Invalid manifest path {}", manifest))));
+        }
+    }
+    Ok(manifest)
+}
+
+// ## Log Stream
+// Follow the log of linting a SHA live, as Server-Sent Events, starting to
+// lint it if we haven't yet. A reconnecting client continues after the
+// `Last-Event-ID` it has seen.
+pub struct LogStream {
+    forge: Arc<Forge>,
+}
+
+impl LogStream {
+    pub fn new(forge: Arc<Forge>) -> LogStream {
+        LogStream { forge: forge }
+    }
+}
+
+impl Handler for LogStream {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let manifest = match manifest_param(req) {
+            Ok(manifest) => manifest,
+            Err(response) => return Ok(response),
+        };
+        let manifest = manifest.as_ref().map(|m| m.as_str());
+        let last_id: usize = req.headers
+                                .get_raw("Last-Event-ID")
+                                .and_then(|values| values.first())
+                                .and_then(|value| String::from_utf8(value.clone()).ok())
+                                .and_then(|value| value.trim().parse().ok())
+                                .unwrap_or(0);
+
+        let router = req.extensions.get::<Router>().unwrap();
+        let project = find_project(router);
+        let sha = router.find("sha").unwrap();
+        let key = lint_key(&*self.forge, &project, sha, manifest);
+
+        let redis: redis::Connection = setup_redis();
+        get_status_or(redis.get(format!("result/{}", key)),
+                      || { schedule_update(&*self.forge, &project, sha, manifest); });
 
-    // First extract all the request information
-    let router = req.extensions.get::<Router>().unwrap();
+        // Only so many streams may be open at once, the others have to try
+        // again later.
+        let stream = match EventStream::new(&key, last_id) {
+            Some(stream) => stream,
+            None => {
+                let mut resp = Response::with((status::ServiceUnavailable,
+                                               "Too many open streams, try again later"));
+                resp.headers.set_raw("Retry-After", vec![b"10".to_vec()]);
+                return Ok(resp);
+            }
+        };
+
+        let mut resp = Response::with((status::Ok, "text/event-stream".parse::<Mime>().unwrap()));
+        resp.headers.set(CacheControl(vec![CacheDirective::NoCache]));
+        resp.body = Some(Box::new(stream));
+        Ok(resp)
+    }
+}
+
+// ## Find Project
+// Most forges identify their projects by `user/repo`, while the plain git
+// source uses the url-encoded remote and the crates registry the crate name.
+fn find_project(router: &Router) -> String {
+    match router.find("remote").or(router.find("name")) {
+        Some(project) => project.to_owned(),
+        None => format!("{0}/{1}", router.find("user").unwrap(), router.find("repo").unwrap()),
+    }
+}
+
+// ## Status Response
+// Render the status report for the given `base_key` – the redis key layout
+// shared by all sources (e.g. `github/user/repo:sha`) – or the `member` of
+// the workspace found there. In this function is where the actual sausage
+// is done.
+fn status_response<F>(url: &iUrl,
+                      forge: &Forge,
+                      project: &str,
+                      base_key: &str,
+                      sha: &str,
+                      member: Option<&str>,
+                      method: &str,
+                      trigger: F)
+                      -> IronResult<Response>
+    where F: Fn()
+{
     let redis: redis::Connection = setup_redis();
 
-    let user = router.find("user").unwrap();
-    let repo = router.find("repo").unwrap();
-    let sha = router.find("sha").unwrap();
-    let filename: Vec<&str> = router.find("method")
-                                    .unwrap_or("badge.svg")
-                                    .rsplitn(2, '.')
-                                    .collect();
+    let filename: Vec<&str> = method.rsplitn(2, '.').collect();
     let (method, ext) = match filename.len() {
         2 => (filename[1], filename[0]),
         _ => (filename[0], ""),
     };
 
     // Use `get_status_or` to look up and map the cached result
-    // or trigger a `schedule_github_update` if that isn't found yet
-    let result_key = format!("result/github/{0}/{1}:{2}", user, repo, sha);
+    // or call the `trigger` if that isn't found yet
+    let result_key = match member {
+        Some(member) => format!("result/{}/{}", base_key, member),
+        None => format!("result/{}", base_key),
+    };
     let (text, color): (String, String) = get_status_or(
         redis.get(result_key.to_owned()),
-        || schedule_github_update(&user, &repo, &sha));
+        trigger);
+    // If we are still waiting for the result of a job, which has been
+    // abandoned before, let the user know we are retrying it.
+    let (text, color) = if text == "linting" && is_retrying(&redis, base_key) {
+        (String::from("retrying"), String::from("orange"))
+    } else {
+        (text, color)
+    };
+    let temporary = text == "linting" || text == "retrying";
 
     // Then render the response
     match method {
         // If this is a simple request for status, just return the result
         "status" => Ok(Response::with((status::Ok, text.to_owned()))),
-        // for the badge, put text, color, base URL and query-parameters from the
-        // incoming requests together to the URL we need to forward it to
-        "badge" => {
-            let target_badge = match req.url.clone().query {
-                Some(query) => format!("{}clippy-{}-{}.{}?{}", BADGE_URL_BASE, text, color, ext, query),
-                _ => format!("{}clippy-{}-{}.{}", BADGE_URL_BASE, text, color, ext),
-            };
-            // while linting, use only temporary redirects, so that the actual
-            // result will be asked for later
-            Ok(Response::with((match text.as_str() {
-                    "linting" => status::TemporaryRedirect,
-                    _ => status::PermanentRedirect
-                },
-                    Redirect(iUrl::parse(&target_badge).unwrap()))))
+        // for the badge, render the text and color into the badge
+        "badge" | "emojibadge" | "fullemojibadge" => {
+            status_badge(url, method, ext, &text, &color, temporary)
         },
-        // emojibadge and fullemojibadge do the same as the request for `badge`,
-        // except that they replace the status with appropriate emoji
-        "emojibadge" => {
-            let emoji = match text.as_str() {
-                "linting" => "👷".to_string(),
-                "failed" => "😱".to_string(),
-                "success" => "👌".to_string(),
-                _ => text.replace("errors", "🤕").replace("warnings", "😟")
+        // If the request is asking for the logs, fetch those.
+        "log" => {
+            match load_log(&redis, base_key) {
+                Some(logs) => Ok(Response::with((status::Ok, logs.join("\n")))),
+                // if there aren't any logs found, we might just started the
+                // process. Let the request know.
+                None => Ok(Response::with((status::Ok, "Started. Please refresh"))),
+            }
+        },
+        // The report page for humans, which follows the log while linting
+        "report" if ext == "html" => {
+            let key = match member {
+                Some(member) => format!("{}/{}", base_key, member),
+                None => base_key.to_owned(),
             };
-
-            let target_badge = match req.url.clone().query {
-                Some(query) => format!("{}clippy-{}-{}.{}?{}", BADGE_URL_BASE, emoji, color, ext, query),
-                _ => format!("{}clippy-{}-{}.{}", BADGE_URL_BASE, emoji, color, ext),
+            let diagnostics = load_diagnostics(&redis, &format!("diagnostics/{}", key));
+            let log = load_log(&redis, base_key).unwrap_or(vec![]);
+            let stream_url = match url.query {
+                Some(ref query) => format!("/{}/sha/{}/{}/log/stream?{}", forge.name(), project, sha, query),
+                None => format!("/{}/sha/{}/{}/log/stream", forge.name(), project, sha),
             };
-            Ok(Response::with((match color.as_str() {
-                    "blue" => status::TemporaryRedirect,
-                    _ => status::PermanentRedirect
-                },
-                    Redirect(iUrl::parse(&target_badge).unwrap()))))
-        },
-        "fullemojibadge" => {
-            let emoji = match text.as_str() {
-                "linting" => "👷".to_string(),
-                "failed" => "😱".to_string(),
-                "success" => "👌".to_string(),
-                _ => text.replace("errors", "🤕").replace("warnings", "😟")
+            let page = html::ReportPage {
+                title: &key,
+                status: &text,
+                color: &color,
+                source_url: &forge.display_url(project, sha),
+                diagnostics: diagnostics.as_ref().map(|d| d.as_slice()),
+                log: &log,
+                stream_url: if temporary { Some(&stream_url) } else { None },
             };
-
-            let target_badge = match req.url.clone().query {
-                Some(query) => format!("{}📎-{}-{}.{}?{}", BADGE_URL_BASE, emoji, color, ext, query),
-                _ => format!("{}📎-{}-{}.{}", BADGE_URL_BASE, emoji, color, ext),
+            let resp = html::render(&page, |file, line| forge.file_url(project, sha, file, line));
+            Ok(Response::with((status::Ok, mime!(Text/Html; Charset=Utf8), resp)))
+        },
+        // The machine-readable summary of the result
+        "result" => {
+            Ok(Response::with((status::Ok,
+                               mime!(Application/Json),
+                               result_json(&redis, base_key, sha, member, &text).to_string())))
+        },
+        // The structured diagnostics stored for the result, as JSON
+        "diagnostics" => {
+            let diagnostics_key = match member {
+                Some(member) => format!("diagnostics/{}/{}", base_key, member),
+                None => format!("diagnostics/{}", base_key),
             };
-            Ok(Response::with((match color.as_str() {
-                    "blue" => status::TemporaryRedirect,
-                    _ => status::PermanentRedirect
-                },
-                    Redirect(iUrl::parse(&target_badge).unwrap()))))
+            match redis.get(diagnostics_key) {
+                Ok(Value::Data(diagnostics)) => {
+                    Ok(Response::with((status::Ok,
+                                       mime!(Application/Json),
+                                       String::from_utf8(diagnostics).unwrap())))
+                }
+                _ => Ok(Response::with((status::NotFound, format!("No diagnostics yet: {}", text)))),
+            }
         },
-        // If the request is asking for the logs, fetch those. This isn't particularly
-        // simple as the Redis library makes the unwrapping a little bit tricky and hard
-        // for rust to guess the proper types. So we have to specify the types and iterator
-        // rather explictly at times.
-        "log" => {
-            let log_key = format!("log/github/{0}/{1}:{2}", user, repo, sha);
-            match redis.lrange(log_key.to_owned(), 0, -1) {
-                Ok(Some(Value::Bulk(logs))) => {
-                    let logs: Vec<String> = logs.iter()
-                                                .map(|ref v| {
-                                                    match **v {
-                                                        Value::Data(ref val) => {
-                                        String::from_utf8(val.to_owned())
-                                            .unwrap()
-                                            .to_owned()
-                                    }
-                                                        _ => "".to_owned(),
-                                                    }
-                                                })
-                                                .collect();
-                    Ok(Response::with((status::Ok, logs.join("\n"))))
+        // The diagnostics in other formats: a SARIF log for code-scanning
+        // tools, JUnit or Checkstyle XML for CI servers, and a Code Climate
+        // report for Gitlab's code quality widget
+        "sarif" | "report.junit" | "report.checkstyle" | "codequality" => {
+            let key = match member {
+                Some(member) => format!("{}/{}", base_key, member),
+                None => base_key.to_owned(),
+            };
+            let diagnostics = match load_diagnostics(&redis, &format!("diagnostics/{}", key)) {
+                Some(diagnostics) => diagnostics,
+                None => {
+                    return Ok(Response::with((status::NotFound,
+                                              format!("No diagnostics yet: {}", text))));
+                }
+            };
+            match method {
+                "sarif" => {
+                    let rustc: Option<String> = redis.hget(format!("meta/{}", base_key), "rustc")
+                                                     .unwrap_or(None);
+                    let sarif = to_sarif(&diagnostics, rustc.as_ref().map(|v| v.as_str()));
+                    Ok(Response::with((status::Ok, mime!(Application/Json), sarif.to_string())))
+                }
+                "report.junit" => {
+                    Ok(Response::with((status::Ok,
+                                       mime!(Application/Xml),
+                                       reports::junit(&key, &diagnostics))))
+                }
+                "report.checkstyle" => {
+                    Ok(Response::with((status::Ok,
+                                       mime!(Application/Xml),
+                                       reports::checkstyle(&diagnostics))))
                 }
-                // if there aren't any logs found, we might just started the
-                // process. Let the request know.
                 _ => {
-                    Ok(Response::with((status::Ok, "Started. Please refresh")))
+                    Ok(Response::with((status::Ok,
+                                       mime!(Application/Json),
+                                       reports::code_quality(&diagnostics).to_string())))
                 }
             }
         },
@@ -224,3 +821,166 @@ pub fn github_handler(req: &mut Request) -> IronResult<Response> {
         _ => Ok(Response::with((status::BadRequest, format!("{} Not Implemented.", method)))),
     }
 }
+
+// ## Queue Status
+// Report how many jobs are waiting in the queue, how many are being
+// processed and by how many workers – of all processes running them – as JSON.
+pub fn queue_status(_: &mut Request) -> IronResult<Response> {
+    let redis: redis::Connection = setup_redis();
+    let (queued, processing) = queue_depth(&redis);
+
+    let mut result: BTreeMap<String, Json> = BTreeMap::new();
+    result.insert("queued".to_owned(), Json::U64(queued as u64));
+    result.insert("processing".to_owned(), Json::U64(processing as u64));
+    result.insert("workers".to_owned(), Json::U64(running_workers(&redis) as u64));
+    Ok(Response::with((status::Ok, mime!(Application/Json), Json::Object(result).to_string())))
+}
+
+// ## Load Log
+// The lines logged for the given `base_key`, if any.
+fn load_log(redis: &redis::Connection, base_key: &str) -> Option<Vec<String>> {
+    let logs: Vec<String> = redis.lrange(format!("log/{}", base_key), 0, -1).unwrap_or(vec![]);
+    if logs.is_empty() {
+        None
+    } else {
+        Some(logs)
+    }
+}
+
+// ## Status Badge
+// Render the `text` and `color` into the badge. emojibadge and fullemojibadge
+// do the same, except that they replace the status with appropriate emoji –
+// and fullemojibadge even the label.
+fn status_badge(url: &iUrl,
+                method: &str,
+                ext: &str,
+                text: &str,
+                color: &str,
+                temporary: bool)
+                -> IronResult<Response> {
+    let message = match method {
+        "badge" => text.to_owned(),
+        _ => {
+            match text {
+                "linting" => "👷".to_string(),
+                "retrying" => "🔁".to_string(),
+                "failed" => "😱".to_string(),
+                "timeout" => "⏰".to_string(),
+                "success" => "👌".to_string(),
+                _ => text.replace("errors", "🤕").replace("warnings", "😟"),
+            }
+        }
+    };
+    let label = match method {
+        "fullemojibadge" => "📎",
+        _ => "clippy",
+    };
+    badge_response(url, label, &message, color, ext, temporary)
+}
+
+// ## Badge Response
+// Render the badge as SVG ourselves, using the `style`, `label`, `color` and
+// `labelColor` query-parameters if given. If the `BADGE_MODE` environment
+// variable is set to `shields` – or any other format than SVG is asked for –
+// we redirect to shields.io instead, passing all query-parameters along.
+// While linting, the badge is only `temporary`, so that the actual result
+// will be asked for later.
+fn badge_response(url: &iUrl,
+                  label: &str,
+                  message: &str,
+                  color: &str,
+                  ext: &str,
+                  temporary: bool)
+                  -> IronResult<Response> {
+    if ext != "svg" || env::var("BADGE_MODE").map(|mode| mode == "shields").unwrap_or(false) {
+        let target_badge = match url.clone().query {
+            Some(query) => format!("{}{}-{}-{}.{}?{}", BADGE_URL_BASE, label, message, color, ext, query),
+            _ => format!("{}{}-{}-{}.{}", BADGE_URL_BASE, label, message, color, ext),
+        };
+        // A relint may change the result, so the redirect is never permanent
+        return Ok(Response::with((status::TemporaryRedirect,
+                                  Redirect(iUrl::parse(&target_badge).unwrap()))));
+    }
+
+    let params: Vec<(String, String)> = url.query
+                                           .as_ref()
+                                           .map(|query| form_urlencoded::parse(query.as_bytes()))
+                                           .unwrap_or(vec![]);
+    let param = |name: &str| {
+        params.iter().find(|&&(ref key, _)| key == name).map(|&(_, ref value)| value.as_str())
+    };
+
+    let svg = render_badge(param("label").unwrap_or(label),
+                           message,
+                           &param("color").and_then(color_code).or(color_code(color)).unwrap(),
+                           &param("labelColor").and_then(color_code).unwrap_or("#555".to_owned()),
+                           Style::from_name(param("style").unwrap_or("flat")));
+
+    let mut resp = Response::with((status::Ok,
+                                   "image/svg+xml".parse::<Mime>().unwrap(),
+                                   svg));
+    // Results for a SHA only change if relinted, so those can be cached for
+    // a few minutes. While linting, nobody should keep the badge around.
+    resp.headers.set(CacheControl(if temporary {
+        vec![CacheDirective::NoCache, CacheDirective::MaxAge(0)]
+    } else {
+        vec![CacheDirective::Public, CacheDirective::MaxAge(300)]
+    }));
+    Ok(resp)
+}
+
+// ## Result JSON
+// Put together everything we know about the result for `base_key` (or its
+// `member`): the status, the counts of warnings and errors, how often every
+// lint fired, when and how long it ran and with which toolchain.
+fn result_json(redis: &redis::Connection,
+               base_key: &str,
+               sha: &str,
+               member: Option<&str>,
+               text: &str)
+               -> Json {
+    let key = match member {
+        Some(member) => format!("{}/{}", base_key, member),
+        None => base_key.to_owned(),
+    };
+    let diagnostics = load_diagnostics(redis, &format!("diagnostics/{}", key));
+    let meta: HashMap<String, String> = redis.hgetall(format!("meta/{}", base_key))
+                                             .unwrap_or(HashMap::new());
+    let meta_field = |name: &str| {
+        meta.get(name).map_or(Json::Null, |value| Json::String(value.to_owned()))
+    };
+
+    let mut result: BTreeMap<String, Json> = BTreeMap::new();
+    result.insert("sha".to_owned(), Json::String(sha.to_owned()));
+    if let Some(member) = member {
+        result.insert("member".to_owned(), Json::String(member.to_owned()));
+    }
+    result.insert("status".to_owned(), Json::String(text.to_owned()));
+
+    if let Some(ref diagnostics) = diagnostics {
+        let count = |level: &str| diagnostics.iter().filter(|d| d.level == level).count() as u64;
+        result.insert("errors".to_owned(), Json::U64(count("error")));
+        result.insert("warnings".to_owned(), Json::U64(count("warning")));
+        let lints: BTreeMap<String, Json> = count_by_lint(diagnostics)
+                                                .into_iter()
+                                                .map(|(lint, count)| (lint, Json::U64(count as u64)))
+                                                .collect();
+        result.insert("lints".to_owned(), Json::Object(lints));
+    }
+
+    result.insert("started".to_owned(), meta_field("started"));
+    result.insert("finished".to_owned(), meta_field("finished"));
+    result.insert("duration".to_owned(),
+                  meta.get("duration")
+                      .and_then(|duration| duration.parse().ok())
+                      .map_or(Json::Null, Json::U64));
+
+    let mut toolchain: BTreeMap<String, Json> = BTreeMap::new();
+    toolchain.insert("rustc".to_owned(), meta_field("rustc"));
+    toolchain.insert("cargo".to_owned(), meta_field("cargo"));
+    toolchain.insert("clippy-service".to_owned(),
+                     Json::String(env!("CARGO_PKG_VERSION").to_owned()));
+    result.insert("toolchain".to_owned(), Json::Object(toolchain));
+
+    Json::Object(result)
+}
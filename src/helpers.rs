@@ -11,35 +11,61 @@ extern crate time;
 extern crate router;
 extern crate mime;
 extern crate tempdir;
-extern crate zip;
+extern crate urlencoded;
+extern crate rustc_serialize;
 
 // and the specific imports we want
-use std::fs::File;
-use std::io::{Read, Cursor, Write};
+use std::io::Read;
 use std::fs;
+use std::path::Path;
 use std::vec::Vec;
 use std::env;
+use std::time::{Duration, Instant};
 use tempdir::TempDir;
 use time::now_utc;
-use zip::ZipArchive;
+use rustc_serialize::json;
 
 use std::slice::SliceConcatExt;
 use redis::{Commands, RedisResult, PipelineCommands, Value};
 
-use iron::headers::Location;
+use iron::headers::{Authorization, Location};
 use iron::prelude::*;
 use iron::status;
 use iron::Url as iUrl;
+use urlencoded::UrlEncodedQuery;
 
 use hyper::client::Client;
 use hyper::header::qitem;
 use hyper::header;
 
-// ## Download And Unzip
-// Given `source_url` and a target directory `tmp_dir` this helper function
-// tries to do download and unzip the given file there. Or returns a String with
-// the error message of what went wrong trying.
-pub fn download_and_unzip(source_url: &str, tmp_dir: &TempDir) -> Result<Vec<String>, String> {
+use clippy::{Diagnostic, Limits, RunError, run as run_clippy};
+use forge::Checkout;
+use workspace::{MemberResults, find_manifest, find_root_manifest, members};
+
+// How long we wait for a server to accept our request or send the next bit of
+// its response, before giving up on it
+const HTTP_TIMEOUT_SECONDS: u64 = 30;
+
+// ## HTTP Client
+// A hyper client which gives up on servers that stop responding, instead of
+// keeping the worker – and the lease on its job – around forever.
+pub fn http_client() -> Client {
+    let mut client = Client::new();
+    client.set_read_timeout(Some(Duration::from_secs(HTTP_TIMEOUT_SECONDS)));
+    client.set_write_timeout(Some(Duration::from_secs(HTTP_TIMEOUT_SECONDS)));
+    client
+}
+
+// ## Download
+// Given `source_url` this helper function tries to download the content
+// into a `u8`-Vector, together with the `Content-Type` the server claimed it
+// to be. Or returns a String with the error message of what went wrong trying.
+// We give up once the body grows larger than `max_bytes`, or the `deadline`
+// passes while it is still coming in.
+pub fn download(source_url: &str,
+                max_bytes: u64,
+                deadline: Instant)
+                -> Result<(Vec<u8>, Option<String>), String> {
 
     // Start by creating a hyper client, which tries to connect and requests
     // the content of that url. Specifically for the github services, make sure
@@ -51,7 +77,7 @@ pub fn download_and_unzip(source_url: &str, tmp_dir: &TempDir) -> Result<Vec<Str
     // In our case, we just want it to accept everything (`*/*` in HTTP-Speak), which
     // translate in the usage of the handy [`mime!`](http://ironframework.io/doc/mime/macro.mime!.html)
     // -macro with `_/_` as the parameter.
-    let client = Client::new();
+    let client = http_client();
     let res = client.get(&source_url.to_owned())
                     .header(header::UserAgent("Clippy/1.0".to_owned()))
                     .header(header::Accept(vec![qitem(mime!(_/_))]))
@@ -60,74 +86,37 @@ pub fn download_and_unzip(source_url: &str, tmp_dir: &TempDir) -> Result<Vec<Str
     // once we are done preparing, let's send the request
     match res.send() {
         // if we have a connection, we will try read the body
-        // into a buffer, a `u8`-Vector.
+        // into a buffer, a `u8`-Vector – chunk by chunk, so we notice when
+        // it gets too large or takes too long.
         Ok(mut res) => {
-            let mut zip_body: Vec<u8> = Vec::new();
-            match res.read_to_end(&mut zip_body) {
-                // if that succeeded, we pass this vector, wrapped into a Cursor
-                // (as ZipArchive requires readable trait) to ZipArchive for
-                // unzipping and processing.
-                Ok(_) => {
-                    match ZipArchive::new(Cursor::new(zip_body)) {
-                        // if ZipArchive was able to read the metadata,
-                        // it is time to unzip its contents
-                        Ok(mut archive) => {
-                            let mut paths: Vec<String> = Vec::new();
-                            // for every file, ZipArchive identified in the response,
-                            // we try to unpack it into the specified `tmp_dir`
-                            for i in 0..archive.len() {
-                                let mut zip_file = archive.by_index(i).unwrap();
-                                let extracted_path = tmp_dir.path().join(zip_file.name());
-                                let full_path = extracted_path.as_path();
-
-                                // Zip uses the size of `0` to inform us that something
-                                // is actually a directory. In that case, we don't try to
-                                // read the content but instead set up the directory
-                                // structure for it: `create_dir_all` recursively creates
-                                // the directory path if not existing.
-                                if zip_file.size() == 0 {
-                                    fs::create_dir_all(full_path).unwrap();
-                                } else {
-                                    // for any other size, we have a proper file.
-                                    // read the uncompressed content into a buffer
-                                    // and write that into the specified target file
-                                    let mut writer = File::create(full_path).unwrap();
-                                    let mut buffer: Vec<u8> = vec![];
-                                    zip_file.read_to_end(&mut buffer).unwrap();
-                                    writer.write(&buffer).unwrap();
-                                    // lastly, add the file path to the vectors of
-                                    // paths to give back
-                                    paths.push(String::from(full_path.to_string_lossy()
-                                                                     .into_owned()));
-                                }
-                            }
-                            // all went fine, all files extracted, return with `Ok`
-                            // and the list of those paths
-                            Ok(paths)
-                        }
-                        // Unfortunately we ran into a ZipArchive Error
-                        Err(zip::result::ZipError::InvalidArchive(error)) |
-                        Err(zip::result::ZipError::UnsupportedArchive(error)) => {
-                            Err(format!("Extracting archive failed: {}", error).to_owned())
-                        }
-                        // ZipArchive told us about a file, which doesn't exist,
-                        // this should really never happen, as we use references
-                        // given by it. The only plausible cause for this is a corrupt
-                        // Zip Archive – so state that.
-                        Err(zip::result::ZipError::FileNotFound) => {
-                            Err(String::from("Zip Archive Corrupt"))
+            let mut body: Vec<u8> = Vec::new();
+            let mut chunk = [0; 64 * 1024];
+            let content_type = res.headers
+                                  .get::<header::ContentType>()
+                                  .map(|content_type| format!("{}", content_type));
+            loop {
+                if Instant::now() >= deadline {
+                    return Err(format!("Downloading {} took too long", source_url));
+                }
+                match res.read(&mut chunk) {
+                    Ok(0) => return Ok((body, content_type)),
+                    Ok(read) => {
+                        body.extend_from_slice(&chunk[..read]);
+                        if body.len() as u64 > max_bytes {
+                            return Err(format!("{} is larger than the {} bytes allowed",
+                                               source_url,
+                                               max_bytes));
                         }
-                        Err(_) => Err(String::from("General IO Error")),
                     }
+                    // The server did respond with something, we couldn't read
+                    // – bubble this error up in the wrapped string for the
+                    // requester to debug.
+                    Err(error) => return Err(format!("Couldn't read response: {}", error)),
                 }
-                // Github did respond with something, Zip couldn't understand
-                // – often a 404 or error on githubs side. Bubble this error up in
-                // the wrapped string for the requester to debug.
-                Err(error) => Err(format!("Couldn't read github response: {}", error)),
             }
         }
-        // We weren't able to connect to github. Let them know what happened.
-        Err(error) => Err(format!("Couldn't connect to github: {}", error)),
+        // We weren't able to connect to the server. Let them know what happened.
+        Err(error) => Err(format!("Couldn't connect to {}: {}", source_url, error)),
     }
     // *Note*: While the `match () => { Ok(x) => ..., Err(x) => ...}` is a little
     // tedious to write (and ugly to read), Rust enforces you to be incredibly specific
@@ -136,21 +125,147 @@ pub fn download_and_unzip(source_url: &str, tmp_dir: &TempDir) -> Result<Vec<Str
     // wished this stupid API provided you with.
 }
 
+// ## Checkout And Lint
+// This function creates a fresh temporary directory (named after `temp_name`),
+// lets the `checkout` function put the sources there and runs clippy for every
+// crate found in the root manifest or the `manifest` path given – all members
+// if it is a workspace.
+// This is the internal function invoked by the workers processing the job
+// queue. It will return an Error or the Report of every member once done.
+pub fn checkout_and_lint<C, F>(temp_name: &str,
+                               checkout: C,
+                               manifest: Option<&str>,
+                               logger: F)
+                               -> Result<MemberResults, RunError>
+// One interesting feature of this function (and a few others) is the scoped
+// `logger` which is passed around. During execution this function is invoked
+// multiple times to report on the current state of affairs.
+    where C: FnOnce(&TempDir, &Limits, Instant) -> Result<Checkout, String>,
+          F: Fn(&str)
+{
+    // The time a job may take starts now – fetching the sources counts, too –
+    // and is shared by all members.
+    let limits = Limits::from_env();
+    let deadline = limits.deadline();
+
+    // We start by creating a temporary directory for our checkout
+    logger("Creating Temp Directory...");
+
+    if let Ok(temp_dir) = TempDir::new(temp_name) {
+
+        // Then we need to get the sources, e.g. by downloading and unzipping
+        // an archive, within the limits of the job. If that failed because
+        // we ran out of time, it is a timeout like any other.
+        let checkout = try!(checkout(&temp_dir, &limits, deadline).map_err(|error| {
+            if Instant::now() >= deadline {
+                RunError::Timeout(limits.timeout)
+            } else {
+                RunError::Failed(error)
+            }
+        }));
+
+        // Once extracted, we report back the files found and try to find the
+        // `Cargo.toml` file asked for – or the root one – and from there all
+        // crates to lint.
+        logger(&format!("Extracted: \n - {}", checkout.files.join("\n - ")));
+        let root_manifest = try!(match manifest {
+            Some(manifest) => {
+                find_manifest(&checkout.root, manifest)
+                    .ok_or(RunError::Failed(format!("No `{}` file found in archive.", manifest)))
+            }
+            None => {
+                find_root_manifest(&checkout.files)
+                    .ok_or(RunError::Failed(String::from("No `Cargo.toml` file found in archive.")))
+            }
+        });
+        let members = try!(members(&root_manifest).map_err(RunError::Failed));
+
+        let repo_root = &checkout.root;
+
+        // The whole checkout is made available to the clippy process, so crates
+        // can reach their path dependencies.
+        let sandbox = temp_dir.path();
+
+        // Execute `run_clippy` in the folder of every member, each with its
+        // own result
+        Ok(members.iter()
+                  .map(|member| {
+                      logger(&format!("Cargo file of {} found in {}",
+                                      member.name,
+                                      member.path.to_string_lossy().into_owned()));
+                      logger(&format!("-------------------------------- Running Clippy on {}",
+                                      member.name));
+                      let result = run_clippy(sandbox, &member.path, &limits, deadline, &logger)
+                                       .map(|mut report| {
+                          // The compiler reports paths relative to the member or
+                          // the workspace root, we want them relative to the root
+                          // of the repository.
+                          let relative = |file: &str| {
+                              [member.path.join(file), root_manifest.parent().unwrap().join(file)]
+                                  .iter()
+                                  .find(|path| path.exists())
+                                  .and_then(|path| path.strip_prefix(repo_root).ok())
+                                  .map(|path| path.to_string_lossy().into_owned())
+                                  .unwrap_or(file.to_owned())
+                          };
+                          for diagnostic in report.diagnostics.iter_mut() {
+                              diagnostic.map_paths(&relative);
+                          }
+                          report
+                      });
+                      (member.name.clone(), result)
+                  })
+                  .collect())
+    } else {
+        // We could run into some IO error, causing the temporary directory creation to
+        // fail. Report that appropriately.
+        Err(RunError::Failed(String::from("Creating temp directory failed")))
+    }
+}
+
+
+// ## List Files
+// Recursively collects the paths of all files found below `dir`, skipping
+// the `.git` folder. Used for sources we don't extract ourselves, but still
+// want to report on the files found.
+pub fn list_files(dir: &Path) -> Vec<String> {
+    let mut paths: Vec<String> = Vec::new();
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            if path.is_dir() {
+                if !path.ends_with(".git") {
+                    paths.extend(list_files(&path));
+                }
+            } else {
+                paths.push(path.to_string_lossy().into_owned());
+            }
+        }
+    }
+    paths
+}
+
 
 // ## Setup Redis
 // Redis is the database backend we use for almost everything. This function
 // looks up the configured REDIS_URL (from the environment) and returns a
 // `redis::Connection` ready to be used.
 pub fn setup_redis() -> redis::Connection {
+    redis_client()
+        .get_connection()
+        .unwrap()
+}
+
+// ## Redis Client
+// The client for the redis we are connected to, e.g. to subscribe to its
+// channels.
+pub fn redis_client() -> redis::Client {
     // Read the environment Variable "REDIS_URL" or fallback to "redis://localhost/"
     // if not found. This variable is the default used by Dokku for the external
     // database we are connected to.
     let url = redis::parse_redis_url(&env::var("REDIS_URL").unwrap_or("redis://localhost/".to_owned()))
                   .unwrap();
-    redis::Client::open(url)
-        .unwrap()
-        .get_connection()
-        .unwrap()
+    redis::Client::open(url).unwrap()
 }
 
 
@@ -159,15 +274,41 @@ pub fn setup_redis() -> redis::Connection {
 // handy function which, given the redis connection, the log-key and the log statement
 // appends it to the redis log list including the current timestamp.
 pub fn log_redis(redis: &redis::Connection, key: &str, value: &str) {
+    let line = format!("{0} {1}", now_utc().rfc3339(), value);
+    let length: RedisResult<usize> = redis.rpush(key, line.clone());
+    // Let everybody following the log live know about the new line, together
+    // with its position in the log.
+    if let Ok(length) = length {
+        let _: RedisResult<()> = redis.publish(key, format!("{}\t{}", length, line));
+    }
+}
+
+
+// ## Store Diagnostics
+// Store the given diagnostics as a JSON list under `key`.
+pub fn store_diagnostics(redis: &redis::Connection, key: &str, diagnostics: &[Diagnostic]) {
     redis::pipe()
-        .cmd("RPUSH")
+        .cmd("SET")
         .arg(key.clone())
-        .arg(format!("{0} {1}", now_utc().rfc3339(), value))
+        .arg(json::encode(&diagnostics).unwrap())
         .ignore()
         .execute(redis);
 }
 
 
+// ## Load Diagnostics
+// Read the diagnostics stored under `key` back, or `None` if there are
+// none (yet).
+pub fn load_diagnostics(redis: &redis::Connection, key: &str) -> Option<Vec<Diagnostic>> {
+    match redis.get(key.to_owned()) {
+        Ok(Value::Data(data)) => {
+            String::from_utf8(data).ok().and_then(|data| json::decode(&data).ok())
+        }
+        _ => None,
+    }
+}
+
+
 // ## Set Redis Cache
 // We use Redis for caching. This handy function sets the value and expires it.
 pub fn set_redis_cache(redis: &redis::Connection, key: &str, value: &str) {
@@ -178,6 +319,82 @@ pub fn set_redis_cache(redis: &redis::Connection, key: &str, value: &str) {
 }
 
 
+// ## Authorized
+// Whether the request carries the token configured in the environment
+// variable `name`, as `Authorization: token <token>` (or `Bearer <token>`).
+// Without a token configured, no request is.
+pub fn authorized(req: &Request, name: &str) -> bool {
+    let token = match env::var(name) {
+        Ok(token) => token,
+        Err(_) => return false,
+    };
+    if token.is_empty() {
+        return false;
+    }
+    match req.headers.get::<Authorization<String>>() {
+        Some(&Authorization(ref value)) => {
+            let mut parts = value.splitn(2, ' ');
+            match (parts.next(), parts.next()) {
+                (Some(scheme), Some(given)) if scheme == "token" || scheme == "Bearer" => {
+                    constant_time_eq(given.trim().as_bytes(), token.as_bytes())
+                }
+                _ => false,
+            }
+        }
+        None => false,
+    }
+}
+
+// ## Constant Time Eq
+// Compare two secrets without bailing out at the first difference, so the
+// time it takes doesn't tell an attacker how much of their guess was right.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// ## Rate Limited
+// Whether the action guarded by `key` has to wait, as it has been done
+// recently. Returns `None` if it may go ahead now, otherwise the number of
+// seconds left to wait.
+pub fn rate_limited(redis: &redis::Connection, key: &str) -> Option<usize> {
+    let ttl: RedisResult<isize> = redis.ttl(key);
+    match ttl {
+        Ok(ttl) if ttl > 0 => Some(ttl as usize),
+        _ => None,
+    }
+}
+
+// ## Rate Limit
+// Remember the action guarded by `key` has been done, so it has to wait for
+// `seconds` before it may be done again.
+pub fn rate_limit(redis: &redis::Connection, key: &str, seconds: usize) {
+    let _: RedisResult<()> = redis.set_ex(key, now_utc().rfc3339().to_string(), seconds);
+}
+
+// ## Status Color
+// Map the status to the appropriate color of the badge
+pub fn status_color(status: &str) -> String {
+    String::from(match status {
+        "success" => "brightgreen",
+        "failed" => "red",
+        "linting" => "blue",
+        "retrying" => "orange",
+        "timeout" => "lightgrey",
+        _ => {
+            // Warnings and Errors contain the count, so we can't
+            // directly map them.
+            if status.ends_with("errors") {
+                "red"
+            } else { // warnings
+                "yellow"
+            }
+        }
+    })
+}
+
 // ## Get Status Or
 // Reads the result of a Redis-Get-Query for the cached result and unpacks the value into
 // the badge relevant information of "text" and badge color we want to use, OR calls
@@ -194,21 +411,8 @@ pub fn get_status_or<F>(result: RedisResult<Option<Value>>, trigger: F) -> (Stri
         // we need to wrap that ourselfes again, before we can process
         Ok(Some(Value::Data(status))) => {
             let status = String::from_utf8(status).unwrap().to_owned();
-            (status.clone(), String::from(match status.as_str() {
-                // Map the status code to the appropriate color
-                "success" => "brightgreen",
-                "failed" => "red",
-                "linting" => "blue",
-                _ => {
-                    // Warnings and Errors contain the count, so we can't
-                    // directly map them.
-                    if status.ends_with("errors") {
-                        "red"
-                    } else { // warnings
-                        "yellow"
-                    }
-                }
-            }))
+            let color = status_color(&status);
+            (status, color)
         }
         _ => {
             // The result given isn't a proper status as we expect it to
@@ -235,6 +439,16 @@ pub fn local_redir(url: &str, source_url: &iUrl) -> IronResult<Response> {
     Ok(resp)
 }
 
+// ## Query Param
+// Look up the first value of the query-parameter `name` of the request, if
+// it was given.
+pub fn query_param(req: &mut Request, name: &str) -> Option<String> {
+    match req.get_ref::<UrlEncodedQuery>() {
+        Ok(query) => query.get(name).and_then(|values| values.first()).cloned(),
+        Err(_) => None,
+    }
+}
+
 // ## fetch
 // Fetches a HTTP URL and returns the content as a String or `None` if anything
 // went wrong. Used as a handy function because Response reading is a little
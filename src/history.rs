@@ -0,0 +1,210 @@
+// Keep track of the SHAs a branch pointed to over time, and what we found there
+
+extern crate redis;
+extern crate rustc_serialize;
+extern crate time;
+
+use std::collections::BTreeMap;
+use std::slice::SliceConcatExt;
+use std::vec::Vec;
+use rustc_serialize::json::{self, Json};
+use redis::{Commands, PipelineCommands, RedisResult};
+use time::{at_utc, get_time, Timespec};
+
+use clippy::Diagnostic;
+use forge::{Forge, base_key, lint_key};
+use helpers::load_diagnostics;
+
+// How many SHAs we remember per branch
+const MAX_ENTRIES: isize = 1000;
+
+// An entry of the history as stored in redis: when we first saw the branch
+// pointing to the SHA, in seconds since the epoch.
+#[derive(RustcEncodable, RustcDecodable)]
+struct Entry {
+    timestamp: i64,
+    sha: String,
+}
+
+// ## Point
+// A point in the history of a branch: the SHA it pointed to since the
+// `timestamp` and the number of warnings and errors found there.
+pub struct Point {
+    pub timestamp: i64,
+    pub sha: String,
+    pub warnings: u32,
+    pub errors: u32,
+}
+
+// The redis list keeping the history of the branch
+fn history_key(forge: &Forge, project: &str, branch: &str) -> String {
+    format!("history/{}", base_key(forge, project, branch))
+}
+
+// ## Record
+// Remember the branch is pointing to the SHA now – unless we knew that
+// already. Only the latest `MAX_ENTRIES` are kept.
+pub fn record(redis: &redis::Connection, forge: &Forge, project: &str, branch: &str, sha: &str) {
+    let key = history_key(forge, project, branch);
+    let last: Option<String> = redis.lindex(key.clone(), -1).unwrap_or(None);
+    if last.and_then(|last| json::decode::<Entry>(&last).ok()).map_or(false, |last| last.sha == sha) {
+        return;
+    }
+    let entry = Entry {
+        timestamp: get_time().sec,
+        sha: sha.to_owned(),
+    };
+    redis::pipe()
+        .cmd("RPUSH").arg(key.clone()).arg(json::encode(&entry).unwrap()).ignore()
+        .cmd("LTRIM").arg(key.clone()).arg(-MAX_ENTRIES).arg(-1).ignore()
+        .execute(redis);
+}
+
+// ## Store Counts
+// Keep the number of errors and warnings among the `diagnostics` of the lint
+// with the given `key` – or of one `member` of it – in its `meta/` hash, so the
+// history doesn't need to look at the diagnostics themselves.
+pub fn store_counts(redis: &redis::Connection,
+                    key: &str,
+                    member: Option<&str>,
+                    diagnostics: &[Diagnostic]) {
+    let (errors, warnings) = count(diagnostics);
+    let (errors_field, warnings_field) = count_fields(member);
+    redis::pipe()
+        .cmd("HSET").arg(format!("meta/{}", key)).arg(errors_field).arg(errors).ignore()
+        .cmd("HSET").arg(format!("meta/{}", key)).arg(warnings_field).arg(warnings).ignore()
+        .execute(redis);
+}
+
+// The errors and warnings among the diagnostics
+fn count(diagnostics: &[Diagnostic]) -> (u32, u32) {
+    let count = |level: &str| diagnostics.iter().filter(|d| d.level == level).count() as u32;
+    (count("error"), count("warning"))
+}
+
+// The fields of the `meta/` hash keeping the counts
+fn count_fields(member: Option<&str>) -> (String, String) {
+    match member {
+        Some(member) => (format!("errors/{}", member), format!("warnings/{}", member)),
+        None => (String::from("errors"), String::from("warnings")),
+    }
+}
+
+// ## Load Counts
+// The errors and warnings of the lint with the given `key` – or one `member`
+// of it. Results stored before we kept the counts are counted once.
+fn load_counts(redis: &redis::Connection, key: &str, member: Option<&str>) -> Option<(u32, u32)> {
+    let (errors_field, warnings_field) = count_fields(member);
+    let counts: RedisResult<(Option<u32>, Option<u32>)> =
+        redis.hget(format!("meta/{}", key), vec![errors_field, warnings_field]);
+    if let Ok((Some(errors), Some(warnings))) = counts {
+        return Some((errors, warnings));
+    }
+    let diagnostics_key = match member {
+        Some(member) => format!("diagnostics/{}/{}", key, member),
+        None => format!("diagnostics/{}", key),
+    };
+    load_diagnostics(redis, &diagnostics_key).map(|diagnostics| {
+        store_counts(redis, key, member, &diagnostics);
+        count(&diagnostics)
+    })
+}
+
+// ## Load
+// The history of the branch, oldest first, with the results found for the
+// `manifest` – and `member` – asked for. SHAs we don't have a result for
+// (yet) are left out.
+pub fn load(redis: &redis::Connection,
+            forge: &Forge,
+            project: &str,
+            branch: &str,
+            manifest: Option<&str>,
+            member: Option<&str>)
+            -> Vec<Point> {
+    let entries: Vec<String> = redis.lrange(history_key(forge, project, branch), 0, -1)
+                                    .unwrap_or(vec![]);
+    entries.iter()
+           .filter_map(|entry| json::decode::<Entry>(entry).ok())
+           .filter_map(|entry| {
+               let key = lint_key(forge, project, &entry.sha, manifest);
+               load_counts(redis, &key, member).map(|(errors, warnings)| {
+                   Point {
+                       timestamp: entry.timestamp,
+                       sha: entry.sha.clone(),
+                       warnings: warnings,
+                       errors: errors,
+                   }
+               })
+           })
+           .collect()
+}
+
+// ## To JSON
+// The history as a JSON array, oldest first.
+pub fn to_json(points: &[Point]) -> Json {
+    Json::Array(points.iter()
+                      .map(|point| {
+                          let mut entry: BTreeMap<String, Json> = BTreeMap::new();
+                          entry.insert("timestamp".to_owned(),
+                                       Json::String(at_utc(Timespec::new(point.timestamp, 0))
+                                                        .rfc3339()
+                                                        .to_string()));
+                          entry.insert("sha".to_owned(), Json::String(point.sha.clone()));
+                          entry.insert("warnings".to_owned(), Json::U64(point.warnings as u64));
+                          entry.insert("errors".to_owned(), Json::U64(point.errors as u64));
+                          Json::Object(entry)
+                      })
+                      .collect())
+}
+
+// ## Sparkline
+// Render the number of warnings and errors over time as a small SVG graph,
+// ending in a dot colored like the latest result on the badge.
+pub fn sparkline(points: &[Point]) -> String {
+    let (width, height, padding) = (200.0, 40.0, 3.0);
+    let last = match points.last() {
+        Some(last) => last,
+        None => {
+            return format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" \
+                            height=\"{1}\"><title>No history yet</title></svg>",
+                           width,
+                           height);
+        }
+    };
+
+    let totals: Vec<f64> = points.iter().map(|point| (point.warnings + point.errors) as f64).collect();
+    let max = totals.iter().cloned().fold(1.0, f64::max);
+    let step = match totals.len() {
+        1 => 0.0,
+        count => (width - 2.0 * padding) / (count - 1) as f64,
+    };
+    let coordinates: Vec<(f64, f64)> = totals.iter()
+                                             .enumerate()
+                                             .map(|(index, total)| {
+                                                 (padding + index as f64 * step,
+                                                  height - padding - total / max * (height - 2.0 * padding))
+                                             })
+                                             .collect();
+    let line: Vec<String> = coordinates.iter()
+                                       .map(|&(x, y)| format!("{:.1},{:.1}", x, y))
+                                       .collect();
+    let (last_x, last_y) = coordinates[coordinates.len() - 1];
+    let color = match (last.errors, last.warnings) {
+        (0, 0) => "#4c1",
+        (0, _) => "#dfb317",
+        _ => "#e05d44",
+    };
+
+    format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\">\
+             <title>{warnings} warnings, {errors} errors</title>\
+             <polyline points=\"{line}\" fill=\"none\" stroke=\"#007ec6\" stroke-width=\"1.5\"/>\
+             <circle cx=\"{x:.1}\" cy=\"{y:.1}\" r=\"2.5\" fill=\"{color}\"/></svg>",
+            width = width,
+            height = height,
+            warnings = last.warnings,
+            errors = last.errors,
+            line = line.join(" "),
+            x = last_x,
+            y = last_y,
+            color = color)
+}
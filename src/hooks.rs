@@ -0,0 +1,167 @@
+// Receive webhooks, so we can lint right after a push – before anybody asks
+
+extern crate iron;
+extern crate redis;
+extern crate rustc_serialize;
+extern crate crypto;
+
+use std::env;
+use std::io::Read;
+use rustc_serialize::json::Json;
+use rustc_serialize::hex::FromHex;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha1::Sha1;
+
+use iron::prelude::*;
+use iron::method::Method;
+use iron::status;
+use redis::Commands;
+
+use helpers::{setup_redis, set_redis_cache, constant_time_eq};
+use forge::{base_key, find_forge, schedule_update};
+use history;
+
+// The largest payload we accept, in bytes. GitHub caps its own at 25MB, but
+// pushes we lint are far smaller than that.
+const MAX_PAYLOAD_BYTES: u64 = 5 * 1024 * 1024;
+
+// ## GitHub Hook
+// Handle the webhooks GitHub sends us, answering `ping`s and linting the new
+// head of a branch pushed to right away, storing it as the branch's SHA.
+// Every payload must be `POST`ed and signed – in the `X-Hub-Signature` header –
+// with the secret of the repository, falling back to the one configured in
+// `GITHUB_WEBHOOK_SECRET`. Secrets of single repositories are provisioned by
+// whoever runs the service, by storing them in redis, e.g.
+// `redis-cli SET hook-secret/github/:user/:repo <secret>`, and giving the same
+// secret to GitHub when adding the webhook. Without any secret for the
+// repository, there is no webhook for it.
+pub fn github_hook(req: &mut Request) -> IronResult<Response> {
+    if req.method != Method::Post {
+        return Ok(Response::with((status::MethodNotAllowed, "Webhooks must be POSTed")));
+    }
+
+    let mut body: Vec<u8> = Vec::new();
+    if req.body.by_ref().take(MAX_PAYLOAD_BYTES + 1).read_to_end(&mut body).is_err() {
+        return Ok(Response::with((status::BadRequest, "Couldn't read payload")));
+    }
+    if body.len() as u64 > MAX_PAYLOAD_BYTES {
+        return Ok(Response::with((status::BadRequest, "Payload too large")));
+    }
+    let payload = match String::from_utf8(body.clone()).ok().and_then(|body| Json::from_str(&body).ok()) {
+        Some(payload) => payload,
+        None => return Ok(Response::with((status::BadRequest, "Payload isn't JSON"))),
+    };
+    let project = match payload.find_path(&["repository", "full_name"]).and_then(|v| v.as_string()) {
+        Some(project) => project.to_owned(),
+        None => return Ok(Response::with((status::BadRequest, "No repository given"))),
+    };
+
+    // Only once we know the payload was sent by GitHub for that repository, we
+    // take it into account. An empty secret is none at all, as anybody could
+    // sign with it.
+    let redis: redis::Connection = setup_redis();
+    let not_empty = |secret: String| if secret.is_empty() { None } else { Some(secret) };
+    let secret: Option<String> = redis.get(format!("hook-secret/github/{}", project))
+                                      .ok()
+                                      .and_then(|secret: Option<String>| secret)
+                                      .and_then(&not_empty)
+                                      .or(env::var("GITHUB_WEBHOOK_SECRET").ok().and_then(&not_empty));
+    let secret = match secret {
+        Some(secret) => secret,
+        None => return Ok(Response::with((status::NotFound, "No webhook configured"))),
+    };
+    match header(req, "X-Hub-Signature") {
+        Some(ref signature) if verify(&secret, &body, signature) => {}
+        _ => return Ok(Response::with((status::Unauthorized, "Invalid signature"))),
+    }
+
+    match header(req, "X-GitHub-Event").as_ref().map(|event| event.as_str()) {
+        Some("ping") => Ok(Response::with((status::Ok, "pong"))),
+        Some("push") => {
+            let branch = payload.find("ref")
+                                .and_then(|v| v.as_string())
+                                .and_then(|reference| {
+                                    if reference.starts_with("refs/heads/") {
+                                        Some(reference["refs/heads/".len()..].to_owned())
+                                    } else {
+                                        None
+                                    }
+                                });
+            let sha = payload.find("after").and_then(|v| v.as_string());
+            let deleted = payload.find("deleted").and_then(|v| v.as_boolean()).unwrap_or(false);
+            match (branch, sha) {
+                (Some(branch), Some(sha)) if !deleted => {
+                    let github = find_forge("github").unwrap();
+                    set_redis_cache(&redis,
+                                    &format!("cached-sha/{}", base_key(&*github, &project, &branch)),
+                                    sha);
+                    history::record(&redis, &*github, &project, &branch, sha);
+                    schedule_update(&*github, &project, sha, None);
+                    Ok(Response::with((status::Accepted, format!("Linting {}:{}", project, sha))))
+                }
+                // Tags and deleted branches have nothing for us to lint
+                _ => Ok(Response::with((status::Ok, "Ignored"))),
+            }
+        }
+        _ => Ok(Response::with((status::Ok, "Ignored"))),
+    }
+}
+
+// The first value of the raw header with the given `name`, if any
+fn header(req: &Request, name: &str) -> Option<String> {
+    req.headers
+       .get_raw(name)
+       .and_then(|values| values.first())
+       .and_then(|value| String::from_utf8(value.clone()).ok())
+}
+
+// ## Verify
+// Check the `signature` of the `body` GitHub sent – `sha1=` followed by the
+// hex-encoded HMAC of the body, keyed with the `secret`.
+fn verify(secret: &str, body: &[u8], signature: &str) -> bool {
+    if !signature.starts_with("sha1=") {
+        return false;
+    }
+    let expected = match signature["sha1=".len()..].from_hex() {
+        Ok(expected) => expected,
+        Err(_) => return false,
+    };
+    let mut hmac = Hmac::new(Sha1::new(), secret.as_bytes());
+    hmac.input(body);
+    constant_time_eq(hmac.result().code(), &expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::verify;
+
+    // The example of GitHub's documentation on validating webhook deliveries
+    const SECRET: &'static str = "It's a Secret to Everybody";
+    const BODY: &'static [u8] = b"Hello, World!";
+
+    #[test]
+    fn accepts_the_signature_of_github() {
+        assert!(verify(SECRET, BODY, "sha1=01dc10d0c83e72ed246219cdd91669667fe2ca59"));
+    }
+
+    #[test]
+    fn rejects_a_wrong_signature() {
+        assert!(!verify(SECRET, BODY, "sha1=01dc10d0c83e72ed246219cdd91669667fe2ca58"));
+        assert!(!verify("another secret", BODY, "sha1=01dc10d0c83e72ed246219cdd91669667fe2ca59"));
+        assert!(!verify(SECRET, b"Hello, World?", "sha1=01dc10d0c83e72ed246219cdd91669667fe2ca59"));
+    }
+
+    #[test]
+    fn rejects_a_signature_without_prefix() {
+        assert!(!verify(SECRET, BODY, "01dc10d0c83e72ed246219cdd91669667fe2ca59"));
+        assert!(!verify(SECRET, BODY, "sha256=01dc10d0c83e72ed246219cdd91669667fe2ca59"));
+    }
+
+    #[test]
+    fn rejects_invalid_hex() {
+        assert!(!verify(SECRET, BODY, "sha1=not-hex-at-all"));
+        assert!(!verify(SECRET, BODY, "sha1=01dc10d0c83e72ed246219cdd91669667fe2ca5"));
+        assert!(!verify(SECRET, BODY, "sha1="));
+    }
+}
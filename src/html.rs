@@ -0,0 +1,205 @@
+// Render the results for humans: an HTML page with the diagnostics and the log
+
+use std::cmp::{max, min};
+use std::collections::BTreeMap;
+use std::slice::SliceConcatExt;
+use std::vec::Vec;
+
+use badge::{color_code, escape};
+use clippy::{Diagnostic, Span, help_url};
+
+static STYLE: &'static str = "body{font-family:sans-serif;max-width:60em;margin:2em auto;color:#333}\
+                              .status{color:#fff;padding:.2em .5em;border-radius:3px}\
+                              table{border-collapse:collapse}td{padding:.2em 1em .2em 0}\
+                              .diagnostic{margin:1em 0}.level{font-weight:bold}\
+                              .error{color:#e05d44}.warning{color:#dfb317}.note{color:#777}\
+                              pre{background:#f6f8fa;padding:.5em;overflow:auto}\
+                              mark{background:#ffe08a}";
+
+// ## Report Page
+// Everything shown on the report page
+pub struct ReportPage<'a> {
+    // e.g. `github/user/repo:sha`
+    pub title: &'a str,
+    pub status: &'a str,
+    pub color: &'a str,
+    // where to look at the sources linted
+    pub source_url: &'a str,
+    // `None` while we are still linting
+    pub diagnostics: Option<&'a [Diagnostic]>,
+    pub log: &'a [String],
+    // where to follow the log live while linting, reloading once done
+    pub stream_url: Option<&'a str>,
+}
+
+// ## Render
+// The page starts with a summary, followed by the diagnostics grouped by
+// lint and by file, with the source they point at – linking to the
+// documentation of the lints and, via `file_url`, to the files on the forge.
+// The raw log is collapsed at the end, unless we are following it live.
+pub fn render<F>(page: &ReportPage, file_url: F) -> String
+    where F: Fn(&str, u64) -> Option<String>
+{
+    let mut body: Vec<String> = Vec::new();
+    body.push(format!("<h1>{}</h1>", escape(page.title)));
+    body.push(format!("<p><span class=\"status\" style=\"background:{}\">{}</span> \
+                       <a href=\"{}\">Browse the sources</a></p>",
+                      color_code(page.color).unwrap_or("#555".to_owned()),
+                      escape(page.status),
+                      escape(page.source_url)));
+
+    if let Some(all) = page.diagnostics {
+        let diagnostics: Vec<&Diagnostic> = all.iter()
+                                               .filter(|d| d.level == "warning" || d.level == "error")
+                                               .collect();
+
+        // The lints found, linking to their documentation and the
+        // diagnostics they reported, with the file each is in
+        let mut lints: BTreeMap<String, Vec<&Diagnostic>> = BTreeMap::new();
+        for &diagnostic in &diagnostics {
+            let lint = diagnostic.code.clone().unwrap_or(diagnostic.level.clone());
+            lints.entry(lint).or_insert(vec![]).push(diagnostic);
+        }
+        if !lints.is_empty() {
+            body.push(String::from("<h2>By lint</h2>"));
+        }
+        for (lint, diagnostics) in lints {
+            body.push(format!("<h3><a href=\"{}\">{}</a> ({})</h3>",
+                              escape(&help_url(&lint)),
+                              escape(&lint),
+                              diagnostics.len()));
+            for diagnostic in diagnostics {
+                body.push(render_diagnostic(diagnostic, true, &file_url));
+            }
+        }
+
+        // Every file with the diagnostics found in it
+        let mut files: BTreeMap<String, Vec<&Diagnostic>> = BTreeMap::new();
+        for diagnostic in diagnostics {
+            let file = diagnostic.span.as_ref().map_or(String::new(), |span| span.file.clone());
+            files.entry(file).or_insert(vec![]).push(diagnostic);
+        }
+        if !files.is_empty() {
+            body.push(String::from("<h2>By file</h2>"));
+        }
+        for (file, diagnostics) in files {
+            body.push(format!("<h3>{}</h3>", link(&escape(&file), file_url(&file, 1))));
+            for diagnostic in diagnostics {
+                body.push(render_diagnostic(diagnostic, false, &file_url));
+            }
+        }
+    }
+
+    // While linting, append the lines to the log as they come in and show
+    // the results once done. The browser reconnects by itself when a stream
+    // ends, but gives up if refused – e.g. with too many streams open – so
+    // then we try again a little later.
+    match page.stream_url {
+        Some(stream_url) => {
+            body.push(format!("<details open><summary>Build log</summary>\
+                               <pre id=\"log\" data-stream=\"{}\"></pre></details>",
+                              escape(stream_url)));
+            body.push(String::from("<script>var log = document.getElementById('log');\
+                                    var source = new EventSource(log.getAttribute('data-stream'));\
+                                    source.onmessage = function(e) { log.textContent += e.data + '\\n'; };\
+                                    source.addEventListener('verdict', function() { \
+                                    source.close(); location.reload(); });\
+                                    source.onerror = function() { \
+                                    if (source.readyState === EventSource.CLOSED) { \
+                                    setTimeout(function() { location.reload(); }, 10000); } };</script>"));
+        }
+        None => {
+            body.push(format!("<details><summary>Build log</summary><pre id=\"log\">{}</pre></details>",
+                              escape(&page.log.join("\n"))));
+        }
+    }
+
+    format!("<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+             <title>clippy: {title}</title><style>{style}</style></head>\
+             <body>{body}</body></html>",
+            title = escape(page.title),
+            style = STYLE,
+            body = body.concat())
+}
+
+// Wrap the `text` into a link, if there is a `url` to link to
+fn link(text: &str, url: Option<String>) -> String {
+    match url {
+        Some(url) => format!("<a href=\"{}\">{}</a>", escape(&url), text),
+        None => text.to_owned(),
+    }
+}
+
+// A single diagnostic: its level, lint, message and where it points at – the
+// line and, if `with_file`, the file – the source with the span highlighted
+// and any notes or help attached.
+fn render_diagnostic<F>(diagnostic: &Diagnostic, with_file: bool, file_url: &F) -> String
+    where F: Fn(&str, u64) -> Option<String>
+{
+    let lint = match diagnostic.code {
+        Some(ref code) => format!(" <a href=\"{}\">{}</a>", escape(&help_url(code)), escape(code)),
+        None => String::new(),
+    };
+    let (location, source) = match diagnostic.span {
+        Some(ref span) => {
+            let location = if with_file {
+                format!("{} line {}", escape(&span.file), span.line_start)
+            } else {
+                format!("line {}", span.line_start)
+            };
+            (link(&location, file_url(&span.file, span.line_start)),
+             format!("<pre>{}</pre>", highlight(span)))
+        }
+        None => (String::new(), String::new()),
+    };
+    let children: Vec<String> = diagnostic.children
+                                          .iter()
+                                          .map(|child| {
+                                              format!("<div class=\"note\">{}: {}</div>",
+                                                      escape(&child.level),
+                                                      escape(&child.message))
+                                          })
+                                          .collect();
+    format!("<div class=\"diagnostic\"><span class=\"level {level}\">{level}</span>{lint}: \
+             {message} {location}{source}{children}</div>",
+            level = escape(&diagnostic.level),
+            lint = lint,
+            message = escape(&diagnostic.message),
+            location = location,
+            source = source,
+            children = children.concat())
+}
+
+// ## Highlight
+// The source lines of the span, with the part it covers marked. Columns are
+// counted in characters, starting at 1.
+fn highlight(span: &Span) -> String {
+    let last = span.text.len().saturating_sub(1);
+    let lines: Vec<String> = span.text
+                                 .iter()
+                                 .enumerate()
+                                 .map(|(index, line)| {
+                                     let chars: Vec<char> = line.chars().collect();
+                                     let start = if index == 0 {
+                                         (span.column_start as usize).saturating_sub(1)
+                                     } else {
+                                         0
+                                     };
+                                     let end = if index == last {
+                                         (span.column_end as usize).saturating_sub(1)
+                                     } else {
+                                         chars.len()
+                                     };
+                                     let start = min(start, chars.len());
+                                     let end = min(max(end, start), chars.len());
+                                     let part = |from: usize, to: usize| {
+                                         escape(&chars[from..to].iter().cloned().collect::<String>())
+                                     };
+                                     format!("{}<mark>{}</mark>{}",
+                                             part(0, start),
+                                             part(start, end),
+                                             part(end, chars.len()))
+                                 })
+                                 .collect();
+    lines.join("\n")
+}
@@ -0,0 +1,534 @@
+// A persistent job queue in redis, worked off by a bounded pool of threads
+
+extern crate redis;
+extern crate rustc_serialize;
+extern crate time;
+extern crate tempdir;
+
+use std::env;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread::{self, JoinHandle};
+use std::sync::Arc;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use std::vec::Vec;
+use rustc_serialize::json;
+use redis::{Commands, PipelineCommands, RedisResult};
+use time::{now_utc, get_time};
+use tempdir::TempDir;
+
+use clippy::{ClippyResult, Diagnostic, Limits, toolchain};
+use forge::{Forge, find_forge, lint_key};
+use helpers::{setup_redis, log_redis, checkout_and_lint, store_diagnostics};
+use history;
+
+// The redis lists holding the jobs waiting to be processed and those a
+// worker is currently busy with. New jobs are pushed to the left of the
+// queue and taken from the right, which gives us FIFO ordering.
+static QUEUE_KEY: &'static str = "queue/jobs";
+static PROCESSING_KEY: &'static str = "queue/processing";
+
+// The sorted set every process running workers registers itself in, with
+// the number of its workers, scored by when it last checked in.
+static WORKERS_KEY: &'static str = "queue/workers";
+
+// Workers hold a lease on the job they are processing, which expires after
+// this many seconds unless it is renewed by their heartbeat. A job without
+// a lease, which isn't queued either, has been abandoned.
+const LEASE_SECONDS: usize = 60;
+
+// How often an abandoned job is retried, before we give up on it.
+const MAX_RETRIES: usize = 3;
+
+// ## Job
+// Everything a worker needs to know to lint a project: the forge by its
+// name, the project, SHA and the optional manifest path. Stored in the
+// queue as JSON.
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct Job {
+    pub forge: String,
+    pub project: String,
+    pub sha: String,
+    pub manifest: Option<String>,
+}
+
+impl Job {
+    // The key the results of this job are stored under
+    pub fn key(&self) -> Option<String> {
+        find_forge(&self.forge).map(|forge| {
+            lint_key(&*forge,
+                     &self.project,
+                     &self.sha,
+                     self.manifest.as_ref().map(|m| m.as_str()))
+        })
+    }
+}
+
+// ## Enqueue
+// Put the `job` with the given `key` into the queue – unless there is a result
+// for it already, or it is queued or being worked on. A job with a log but
+// neither of those has been abandoned – e.g. the process died while linting –
+// and is put back into the queue as a retry, until we give up after
+// `MAX_RETRIES`.
+// We check and push inside a redis transaction, watching all keys involved,
+// so that two requests coming in at the same time can't both enqueue it.
+// Returns whether the job has been put into the queue.
+pub fn enqueue(redis: &redis::Connection, key: &str, job: &Job) -> bool {
+    let result_key = format!("result/{}", key);
+    let log_key = format!("log/{}", key);
+    let queued_key = format!("queued/{}", key);
+    let lease_key = format!("lease/{}", key);
+    let retries_key = format!("retries/{}", key);
+    let payload = json::encode(job).unwrap();
+
+    let queued: RedisResult<bool> = redis::transaction(redis,
+                                                     &[log_key.clone(),
+                                                       result_key.clone(),
+                                                       queued_key.clone(),
+                                                       lease_key.clone()],
+                                                     |pipe| {
+        let taken_care_of = [&result_key, &queued_key, &lease_key]
+                                .iter()
+                                .any(|key| redis.exists(key.as_str()).unwrap_or(true));
+        if taken_care_of {
+            // we have been alerted, the key already existed
+            // so someone else is taking care of it. We should stop now.
+            return Ok(Some(false));
+        }
+
+        if redis.exists(log_key.clone()).unwrap_or(true) {
+            let retries: usize = redis.get(retries_key.clone()).unwrap_or(0);
+            if retries >= MAX_RETRIES {
+                return pipe.cmd("RPUSH")
+                           .arg(log_key.clone())
+                           .arg(format!("{0} Giving up after {1} retries",
+                                        now_utc().rfc3339(),
+                                        retries))
+                           .ignore()
+                           .cmd("SET")
+                           .arg(result_key.clone())
+                           .arg("failed")
+                           .ignore()
+                           .cmd("PUBLISH")
+                           .arg(format!("done/{}", key))
+                           .arg("failed")
+                           .ignore()
+                           .query(redis)
+                           .map(|done: Option<()>| done.map(|_| false));
+            }
+            pipe.cmd("RPUSH")
+                .arg(log_key.clone())
+                .arg(format!("{0} abandoned, retrying {1}", now_utc().rfc3339(), key))
+                .ignore()
+                .cmd("INCR")
+                .arg(retries_key.clone())
+                .ignore();
+        } else {
+            pipe.cmd("RPUSH")
+                .arg(log_key.clone())
+                .arg(format!("{0} queued {1}", now_utc().rfc3339(), key))
+                .ignore();
+        }
+        pipe.cmd("SET")
+            .arg(queued_key.clone())
+            .arg(1)
+            .ignore()
+            .cmd("LPUSH")
+            .arg(QUEUE_KEY)
+            .arg(payload.clone())
+            .ignore()
+            .query(redis)
+            .map(|done: Option<()>| done.map(|_| true))
+    });
+    match queued {
+        Ok(queued) => queued,
+        Err(error) => {
+            warn!("Couldn't enqueue {}: {}", key, error);
+            false
+        }
+    }
+}
+
+// ## Reset
+// Throw away everything we know about the job with the given `key` – the
+// result, log, diagnostics and meta data, including those of all members – so
+// it can be enqueued afresh. Refuses to, returning `false`, while the job is
+// queued or being processed.
+pub fn reset(redis: &redis::Connection, key: &str) -> bool {
+    let queued_key = format!("queued/{}", key);
+    let lease_key = format!("lease/{}", key);
+    let members_key = format!("members/{}", key);
+    let mut keys: Vec<String> = ["result", "log", "diagnostics", "meta", "retries", "members"]
+                                    .iter()
+                                    .map(|prefix| format!("{}/{}", prefix, key))
+                                    .collect();
+    let members: Vec<String> = redis.smembers(members_key.clone()).unwrap_or(vec![]);
+    for member in members {
+        keys.push(format!("result/{}/{}", key, member));
+        keys.push(format!("diagnostics/{}/{}", key, member));
+    }
+
+    let reset: RedisResult<bool> = redis::transaction(redis,
+                                                      &[queued_key, lease_key, members_key],
+                                                      |pipe| {
+        if is_busy(redis, key) {
+            return Ok(Some(false));
+        }
+        pipe.cmd("DEL")
+            .arg(keys.clone())
+            .ignore()
+            .query(redis)
+            .map(|done: Option<()>| done.map(|_| true))
+    });
+    reset.unwrap_or(false)
+}
+
+// ## Is Busy
+// Whether the job for `key` is queued or being processed right now.
+pub fn is_busy(redis: &redis::Connection, key: &str) -> bool {
+    [format!("queued/{}", key), format!("lease/{}", key)]
+        .iter()
+        .any(|key| redis.exists(key.as_str()).unwrap_or(true))
+}
+
+// ## Is Retrying
+// Whether the job for `key` has been abandoned before and is being retried.
+pub fn is_retrying(redis: &redis::Connection, key: &str) -> bool {
+    let retries: usize = redis.get(format!("retries/{}", key)).unwrap_or(0);
+    retries > 0
+}
+
+// ## Queue Depth
+// How many jobs are waiting and how many are being processed right now.
+pub fn queue_depth(redis: &redis::Connection) -> (usize, usize) {
+    (redis.llen(QUEUE_KEY).unwrap_or(0),
+     redis.llen(PROCESSING_KEY).unwrap_or(0))
+}
+
+// ## Worker Count
+// The number of worker threads to run, configured via the `WORKERS`
+// environment variable, falling back to 2.
+pub fn worker_count() -> usize {
+    env::var("WORKERS").ok().and_then(|count| count.parse().ok()).unwrap_or(2)
+}
+
+// ## Start Workers
+// Spawn `count` worker threads, each taking one job at a time from the queue,
+// and the reaper looking for abandoned jobs. Returns the handles of all of
+// them, which never finish.
+pub fn start_workers(count: usize) -> Vec<JoinHandle<()>> {
+    let mut threads: Vec<JoinHandle<()>> = (0..count).map(|_| thread::spawn(work)).collect();
+    if count > 0 {
+        threads.push(thread::spawn(move || register(count)));
+    }
+    threads.push(thread::spawn(reap));
+    threads
+}
+
+// ## Register
+// Let everybody know this process runs `count` workers, by checking in every
+// so often – processes which stopped doing so are gone.
+fn register(count: usize) {
+    let redis: redis::Connection = setup_redis();
+    let started = get_time();
+    let member = format!("{0}-{1}.{2}\t{3}",
+                         env::var("HOSTNAME").unwrap_or(String::from("worker")),
+                         started.sec,
+                         started.nsec,
+                         count);
+    loop {
+        let _: RedisResult<()> = redis.zadd(WORKERS_KEY, member.clone(), get_time().sec);
+        thread::sleep(Duration::from_secs(LEASE_SECONDS as u64 / 3));
+    }
+}
+
+// ## Running Workers
+// The number of workers of all processes, which checked in lately.
+pub fn running_workers(redis: &redis::Connection) -> usize {
+    let _: RedisResult<()> = redis.zrembyscore(WORKERS_KEY,
+                                               "-inf",
+                                               get_time().sec - LEASE_SECONDS as i64);
+    let processes: Vec<String> = redis.zrange(WORKERS_KEY, 0, -1).unwrap_or(vec![]);
+    processes.iter()
+             .filter_map(|process| process.rsplit('\t').next().and_then(|count| count.parse().ok()))
+             .fold(0, |sum, count: usize| sum + count)
+}
+
+// ## Work
+// The loop every worker thread runs: block until there is a job in the queue,
+// atomically moving it into the processing list, take the lease on it,
+// process it and remove it from there once done.
+// Should processing panic, we mustn't leave the job behind with a lease kept
+// alive forever, nor lose the worker: we give up the lease, put the job back
+// into the queue as a retry – which gives up on it after `MAX_RETRIES` – and
+// carry on with the next one.
+fn work() {
+    let redis: redis::Connection = setup_redis();
+    loop {
+        let next: RedisResult<String> = redis.brpoplpush(QUEUE_KEY, PROCESSING_KEY, 0);
+        match next {
+            Ok(payload) => {
+                let mut retry: Option<(String, Job)> = None;
+                match json::decode::<Job>(&payload) {
+                    Ok(job) => {
+                        match job.key() {
+                            Some(key) => {
+                                let processed = {
+                                    let _lease = Lease::take(&redis, &key);
+                                    panic::catch_unwind(AssertUnwindSafe(|| process(&redis, &job)))
+                                };
+                                let mut cleanup = redis::pipe();
+                                cleanup.cmd("DEL").arg(format!("lease/{}", key)).ignore();
+                                if processed.is_ok() {
+                                    cleanup.cmd("DEL").arg(format!("retries/{}", key)).ignore();
+                                } else {
+                                    warn!("Processing {} panicked", key);
+                                    log_redis(&redis, &format!("log/{}", key), "Processing panicked");
+                                    retry = Some((key, job));
+                                }
+                                let _: RedisResult<()> = cleanup.query(&redis);
+                            }
+                            None => warn!("Unknown forge {} in job {:?}", job.forge, job),
+                        }
+                    }
+                    Err(error) => warn!("Dropping invalid job {}: {}", payload, error),
+                }
+                let _: RedisResult<()> = redis.lrem(PROCESSING_KEY, 1, payload);
+                if let Some((key, job)) = retry {
+                    enqueue(&redis, &key, &job);
+                }
+            }
+            Err(error) => {
+                warn!("Couldn't fetch job: {}", error);
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
+    }
+}
+
+// ## Lease
+// Marks the job for `key` as no longer queued but being processed by us. The
+// lease expires after `LEASE_SECONDS`, so a heartbeat thread keeps renewing
+// it – until the lease is dropped, however processing ended.
+struct Lease {
+    beating: Arc<AtomicBool>,
+}
+
+impl Lease {
+    fn take(redis: &redis::Connection, key: &str) -> Lease {
+        let lease_key = format!("lease/{}", key);
+        redis::pipe()
+            .cmd("SET").arg(lease_key.clone()).arg(now_utc().rfc3339().to_string())
+            .arg("EX").arg(LEASE_SECONDS).ignore()
+            .cmd("DEL").arg(format!("queued/{}", key)).ignore()
+            .execute(redis);
+
+        let beating = Arc::new(AtomicBool::new(true));
+        let still_beating = beating.clone();
+        thread::spawn(move || {
+            let redis: redis::Connection = setup_redis();
+            loop {
+                thread::sleep(Duration::from_secs(LEASE_SECONDS as u64 / 3));
+                if !still_beating.load(Ordering::SeqCst) {
+                    break;
+                }
+                let _: RedisResult<()> = redis.expire(lease_key.clone(), LEASE_SECONDS);
+            }
+        });
+        Lease { beating: beating }
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.beating.store(false, Ordering::SeqCst);
+    }
+}
+
+// ## Reap
+// Every so often look through the jobs marked as processing. Those without a
+// lease belong to a worker that died (or the whole process was restarted), so
+// we take them out and put them back into the queue as a retry. As a worker
+// takes the lease just after taking the job, we only consider a job abandoned
+// if it had no lease on two rounds in a row.
+fn reap() {
+    let redis: redis::Connection = setup_redis();
+    let mut suspects: HashSet<String> = HashSet::new();
+    loop {
+        thread::sleep(Duration::from_secs(LEASE_SECONDS as u64));
+        let processing: Vec<String> = redis.lrange(PROCESSING_KEY, 0, -1).unwrap_or(vec![]);
+        let mut without_lease: HashSet<String> = HashSet::new();
+        for payload in processing {
+            let job = match json::decode::<Job>(&payload) {
+                Ok(job) => job,
+                Err(_) => continue,
+            };
+            if let Some(key) = job.key() {
+                if redis.exists(format!("lease/{}", key)).unwrap_or(true) {
+                    continue;
+                }
+                if suspects.contains(&payload) {
+                    warn!("Job {} has been abandoned, requeueing", key);
+                    let _: RedisResult<()> = redis.lrem(PROCESSING_KEY, 1, payload.clone());
+                    let _: RedisResult<()> = redis.del(format!("queued/{}", key));
+                    enqueue(&redis, &key, &job);
+                } else {
+                    without_lease.insert(payload);
+                }
+            }
+        }
+        suspects = without_lease;
+    }
+}
+
+// ## Process
+// Execute the `job` – the fetching and running of clippy – logging and storing
+// its result under the appropriate redis keys.
+pub fn process(redis: &redis::Connection, job: &Job) {
+    let forge = match find_forge(&job.forge) {
+        Some(forge) => forge,
+        None => {
+            warn!("Unknown forge {} in job {:?}", job.forge, job);
+            return;
+        }
+    };
+
+    let base_key = lint_key(&*forge, &job.project, &job.sha, job.manifest.as_ref().map(|m| m.as_str()));
+    let temp_name = base_key.replace("/", "_").replace(":", "_");
+
+    let result_key = format!("result/{}", base_key).to_owned();
+    let log_key = format!("log/{}", base_key).to_owned();
+    let diagnostics_key = format!("diagnostics/{}", base_key).to_owned();
+    let meta_key = format!("meta/{}", base_key).to_owned();
+
+    let logger = |statement: &str| log_redis(redis, &log_key, statement);
+    logger(&format!("started processing {}", base_key));
+    logger(&format!("Linting {}", forge.display_url(&job.project, &job.sha)));
+
+    // Let the forge know we are on it – and where to follow along
+    let context = match job.manifest {
+        Some(ref manifest) => format!("clippy ({})", manifest),
+        None => String::from("clippy"),
+    };
+    let target_url = log_url(&*forge, job);
+    let report = |status: &str| {
+        if let Err(error) = forge.report_status(&job.project,
+                                                &job.sha,
+                                                &context,
+                                                status,
+                                                target_url.as_ref().map(|url| url.as_str())) {
+            logger(&format!("Couldn't report status: {}", error));
+        }
+    };
+    report("linting");
+
+    // Remember when we started and what we are running with, so we can
+    // report on that later
+    let started = get_time();
+    let mut meta = redis::pipe();
+    meta.cmd("HSET").arg(meta_key.clone()).arg("started").arg(now_utc().rfc3339().to_string()).ignore();
+    for (tool, version) in toolchain() {
+        meta.cmd("HSET").arg(meta_key.clone()).arg(tool).arg(version).ignore();
+    }
+    meta.execute(redis);
+
+    // We are ready to roll: check out the sources, run clippy and parse the
+    // result. Every member gets its own result stored, while the overall
+    // status adds up the counts of all of them. If any member
+    // – or the update itself – failed, report the error and set the status
+    // to "failed", or "timeout" if it ran out of time.
+    let checkout = |temp_dir: &TempDir, limits: &Limits, deadline: Instant| {
+        forge.checkout(&job.project, &job.sha, temp_dir, limits, deadline, &logger)
+    };
+    let text: String = match checkout_and_lint(&temp_name,
+                                               checkout,
+                                               job.manifest.as_ref().map(|m| m.as_str()),
+                                               &logger) {
+        Ok(results) => {
+            let mut total = Ok(ClippyResult::Success);
+            let mut all_diagnostics: Vec<Diagnostic> = Vec::new();
+            for (member, result) in results {
+                let member_text = match result {
+                    Ok(ref report) => {
+                        // Keep the diagnostics of every member as structured
+                        // JSON, so they can be queried later
+                        store_diagnostics(redis,
+                                          &format!("{}/{}", diagnostics_key, member),
+                                          &report.diagnostics);
+                        history::store_counts(redis, &base_key, Some(member.as_str()), &report.diagnostics);
+                        all_diagnostics.extend(report.diagnostics.iter().cloned());
+                        report.result.status()
+                    }
+                    Err(ref error) => {
+                        log_redis(redis, &log_key, &format!("{} failed: {}", member, error));
+                        error.status()
+                    }
+                };
+                log_redis(redis, &log_key, &format!("{}: {}", member, member_text));
+                // Remember the members we stored results for, so we know
+                // what to throw away on a relint.
+                redis::pipe()
+                    .cmd("SET")
+                    .arg(format!("{}/{}", result_key, member))
+                    .arg(member_text)
+                    .ignore()
+                    .cmd("SADD")
+                    .arg(format!("members/{}", base_key))
+                    .arg(member.clone())
+                    .ignore()
+                    .execute(redis);
+                // the first member failing – or timing out – decides the
+                // overall status
+                total = match (total, result) {
+                    (Ok(total), Ok(report)) => Ok(total.combine(&report.result)),
+                    (Ok(_), Err(error)) => Err(error.status()),
+                    (Err(status), _) => Err(status),
+                };
+            }
+            store_diagnostics(redis, &diagnostics_key, &all_diagnostics);
+            history::store_counts(redis, &base_key, None, &all_diagnostics);
+            match total {
+                Ok(total) => total.status(),
+                Err(status) => status,
+            }
+        }
+        Err(error) => {
+            log_redis(redis, &log_key, &format!("Failed: {}", error));
+            error.status()
+        }
+    };
+
+    // log the output from clippy and set the result into the redis cache.
+    // we are done with this job.
+    log_redis(redis,
+              &log_key,
+              &format!("------------------------------------------\n Clippy's final \
+                        verdict: {}",
+                       text));
+    let duration = get_time() - started;
+    redis::pipe()
+        .cmd("HSET").arg(meta_key.clone()).arg("finished").arg(now_utc().rfc3339().to_string()).ignore()
+        .cmd("HSET").arg(meta_key.clone()).arg("duration").arg(duration.num_seconds()).ignore()
+        .cmd("SET").arg(result_key).arg(text.clone()).ignore()
+        .cmd("PUBLISH").arg(format!("done/{}", base_key)).arg(text.clone()).ignore()
+        .execute(redis);
+    report(&text);
+}
+
+// ## Log URL
+// Where humans can follow the log of the `job`, if we know the public URL of
+// the service from the `PUBLIC_URL` environment variable.
+fn log_url(forge: &Forge, job: &Job) -> Option<String> {
+    env::var("PUBLIC_URL").ok().map(|base| {
+        let url = format!("{0}/{1}/sha/{2}/{3}/log",
+                          base.trim_right_matches('/'),
+                          forge.name(),
+                          job.project,
+                          job.sha);
+        match job.manifest {
+            Some(ref manifest) => format!("{}?manifest={}", url, manifest),
+            None => url,
+        }
+    })
+}
@@ -0,0 +1,63 @@
+// **The clippy-service library**, shared by the web server in `src/main.rs`
+// and the worker in `src/worker.rs`.
+
+// Features and macros need to be defined in the root of a crate. In this
+// case, we need a few macros as well as the new slice-concat feature, a join
+// on a vector (list) of strings.
+#![feature(slice_concat_ext)]
+
+// Further more we define our dependencies on external crates.
+// First everything that is directly related to the iron framework
+// are using to build our web project on
+extern crate iron;
+extern crate urlencoded;
+extern crate router;
+
+// Secondly all high level libraries, like serializers, hyper and redis
+extern crate rustc_serialize;
+extern crate hyper;
+extern crate redis;
+
+// And last all the very common utils, like loggers, tempdir, time
+// We want to use the log macros globally
+#[macro_use]
+extern crate log;
+
+// the macro to define Mime types
+#[macro_use]
+extern crate mime;
+
+extern crate tempdir;
+extern crate url;
+extern crate time;
+extern crate zip;
+extern crate flate2;
+extern crate tar;
+extern crate semver;
+extern crate toml;
+extern crate crypto;
+
+// Next, we need to _register_ the other modules of this crate here, using the `mod` keyword:
+// We want it to use the handlers, helpers, archive, badge, forge, github, gitlab,
+// git, crates, workspace, jobs, hooks, diff, history, sarif, reports, html, stream
+// and clippy modules (all in their respective files)
+
+pub mod handlers;
+pub mod helpers;
+pub mod archive;
+pub mod badge;
+pub mod forge;
+pub mod github;
+pub mod gitlab;
+pub mod git;
+pub mod crates;
+pub mod workspace;
+pub mod jobs;
+pub mod hooks;
+pub mod diff;
+pub mod history;
+pub mod sarif;
+pub mod reports;
+pub mod html;
+pub mod stream;
+pub mod clippy;
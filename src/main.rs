@@ -1,9 +1,8 @@
 // **Run as an application, this is where execution starts.**
 
-// Features and macros need to be defined in the main entry point
-// of a project. In this case, we need a few macros as well as
-// the new slice-concat feature, a join on a vector (list) of strings.
-#![feature(slice_concat_ext)]
+// Everything but the web server itself lives in the `clippy_service` library
+// (see `src/lib.rs`), which the `clippy-worker` binary shares with us.
+extern crate clippy_service;
 
 // Further more we define our app dependencies on external crates.
 // First everything that is directly related to the iron framework
@@ -16,42 +15,52 @@ extern crate mount;
 #[macro_use]
 extern crate router;
 
-// Secondly all high level libraries, like serializers, hyper and redis
-extern crate rustc_serialize;
-extern crate hyper;
-extern crate redis;
-
-// And last all the very common utils, like loggers, tempdir, time
-// We want to use the log macros globally
+// And the loggers. We want to use the log macros globally
 #[macro_use]
 extern crate log;
 extern crate env_logger;
 
-// the macro to define Mime types
-#[macro_use]
-extern crate mime;
-
-extern crate tempdir;
-extern crate url;
-extern crate time;
-extern crate zip;
-
-// Next, we need to _register_ the other modules of this crate here, using the `mod` keyword:
-// We want it to use the handlers, helpers, github and clippy modules (all in their
-// respective files)
-
-mod handlers;
-mod helpers;
-mod github;
-mod clippy;
-
 // Then we  _import_ the things specifically needed for this particular module
 // again starting with iron, its specifics and lastly common libs
 use iron::prelude::*;
 use staticfile::Static;
 use mount::Mount;
+use router::Router;
 
 use std::path::Path;
+use std::sync::Arc;
+
+use clippy_service::{handlers, hooks, jobs};
+use clippy_service::forge::{Forge, find_forge};
+
+
+// **Forge Routes** builds the routing table every forge offers below its mount
+// point. We are using the [`router!`-macro](http://ironframework.io/doc/router/macro.router!.html)
+// here because it offers a much more readable way of specifying the routing
+// table:
+// ```
+//   METHOD "URL/:with_keywords" => HANDLER
+// ```
+//
+// A single member of a workspace can be asked for by adding its name after the
+// branch or SHA. `POST`ing to `relint` forces a fresh run. Pull requests are
+// found under `pull/:number`, reporting only what they change, and any two
+// SHAs or branches can be compared under `compare/:base...:head`. The log can
+// be followed live under `log/stream`.
+fn forge_routes(forge: Arc<Forge>) -> Router {
+    router!(
+        get "/:user/:repo/compare/:range" => handlers::Compare::new(forge.clone()),
+        get "/:user/:repo/pull/:number/:method" => handlers::PullHandler::new(forge.clone()),
+        post "/sha/:user/:repo/:sha/relint" => handlers::Relint::new(forge.clone()),
+        post "/:user/:repo/:branch/relint" => handlers::Relint::new(forge.clone()),
+        get "/sha/:user/:repo/:sha/log/stream" => handlers::LogStream::new(forge.clone()),
+        get "/sha/:user/:repo/:sha/:member/:method" => handlers::ShaHandler::new(forge.clone()),
+        get "/sha/:user/:repo/:sha/:method" => handlers::ShaHandler::new(forge.clone()),
+        get "/:user/:repo/:branch/:member/:method" => handlers::Finder::new(forge.clone()),
+        get "/:user/:repo/:branch/:method" => handlers::Finder::new(forge.clone()),
+        get "/:user/:repo/:method" => handlers::Finder::new(forge.clone())
+    )
+}
 
 
 // **The `main` function** in `src/main.rs` is the entry point for our command when it will
@@ -66,20 +75,50 @@ fn main() {
     // on the first part of the url.
     let mut mount = Mount::new();
 
-    // Everything starting with `/github/` should be routed to our github handlers
-    // in `handler.rs`.
-    // We are using the [`router!`-macro](http://ironframework.io/doc/router/macro.router!.html)
-    // here because it offers a much more readable way of specifying the routing
-    // table:
-    // ```
-    //   METHOD "URL/:with_keywords" => HANDLER
-    // ```
-    mount.mount("/github/", router!(
-        get "/sha/:user/:repo/:sha/:method" => handlers::github_handler,
-        get "/:user/:repo/:branch/:method" => handlers::github_finder,
-        get "/:user/:repo/:method" => handlers::github_finder
+    // Everything starting with `/github/` should be routed to our handlers
+    // in `handler.rs` using the Github forge, everything starting with `/gitlab/`
+    // the same using the Gitlab forge. Which Gitlab instance is asked can be
+    // configured via the `GITLAB_URL` environment variable.
+    for name in &["github", "gitlab"] {
+        mount.mount(&format!("/{}/", name), forge_routes(find_forge(name).unwrap()));
+    }
+
+    // Any other git remote can be linted under `/git/`, by passing the
+    // url-encoded remote URL instead of user and repo, for example
+    // `/git/https%3A%2F%2Fgit.example.com%2Fcrate.git/master/badge.svg`.
+    let git = find_forge("git").unwrap();
+    mount.mount("/git/", router!(
+        post "/sha/:remote/:sha/relint" => handlers::Relint::new(git.clone()),
+        post "/:remote/:branch/relint" => handlers::Relint::new(git.clone()),
+        get "/:remote/compare/:range" => handlers::Compare::new(git.clone()),
+        get "/sha/:remote/:sha/log/stream" => handlers::LogStream::new(git.clone()),
+        get "/sha/:remote/:sha/:member/:method" => handlers::ShaHandler::new(git.clone()),
+        get "/sha/:remote/:sha/:method" => handlers::ShaHandler::new(git.clone()),
+        get "/:remote/:branch/:member/:method" => handlers::Finder::new(git.clone()),
+        get "/:remote/:branch/:method" => handlers::Finder::new(git.clone()),
+        get "/:remote/:method" => handlers::Finder::new(git.clone())
+    ));
+
+    // Published crates are found under `/crates/`, where the version takes the
+    // place of the SHA, e.g. `/crates/:name/:version/badge.svg`. Without a version
+    // we look up the newest one in the registry index configured via `CRATES_INDEX_URL`.
+    let crates = find_forge("crates").unwrap();
+    mount.mount("/crates/", router!(
+        post "/sha/:name/:sha/relint" => handlers::Relint::new(crates.clone()),
+        post "/:name/:sha/relint" => handlers::Relint::new(crates.clone()),
+        get "/sha/:name/:sha/log/stream" => handlers::LogStream::new(crates.clone()),
+        get "/sha/:name/:sha/:method" => handlers::ShaHandler::new(crates.clone()),
+        get "/:name/:sha/:method" => handlers::ShaHandler::new(crates.clone()),
+        get "/:name/:method" => handlers::Finder::new(crates.clone())
     ));
 
+    // GitHub can tell us about pushes via a webhook to `/hooks/github`, so we
+    // lint them right away.
+    mount.mount("/hooks/github", hooks::github_hook);
+
+    // The state of the job queue can be looked at under `/queue`
+    mount.mount("/queue", handlers::queue_status);
+
     // Secondly we have some static files in the static/ folder we'd like to have served.
     // *Note*: We have to define them seperately as Static _does not_ serve recursively
     // at the time of writing.
@@ -87,6 +126,11 @@ fn main() {
     mount.mount("/docs/", Static::new(Path::new("static/docs/")));
     mount.mount("/", Static::new(Path::new("static")));
 
+    // Start the workers processing the job queue in the background. How many
+    // run at the same time can be configured via the `WORKERS` environment variable.
+    // Set it to `0` when running separate `clippy-worker` processes instead.
+    jobs::start_workers(jobs::worker_count());
+
     // Send a message to the console, letting the user know we are (going to be) up
     warn!("Server running at 8080");
 
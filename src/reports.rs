@@ -0,0 +1,272 @@
+// Render the diagnostics in the formats CI servers understand
+
+extern crate rustc_serialize;
+extern crate crypto;
+
+use std::collections::{BTreeMap, HashMap};
+use std::slice::SliceConcatExt;
+use std::vec::Vec;
+use rustc_serialize::json::Json;
+use crypto::digest::Digest;
+use crypto::md5::Md5;
+
+use badge::escape;
+use clippy::Diagnostic;
+use diff::fingerprint;
+
+static XML_HEADER: &'static str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n";
+
+// The lint – or the level, if it has none – the diagnostic is about
+fn lint(diagnostic: &Diagnostic) -> String {
+    diagnostic.code.clone().unwrap_or(diagnostic.level.clone())
+}
+
+// Only warnings and errors are reported, notes and help belong to those
+fn relevant(diagnostics: &[Diagnostic]) -> Vec<&Diagnostic> {
+    diagnostics.iter().filter(|d| d.level == "warning" || d.level == "error").collect()
+}
+
+// ## JUnit
+// A JUnit XML report with a test case per warning or error, which fails for
+// warnings and errs for errors – or a single passing one, if all is well –
+// in a test suite with the given `name`.
+pub fn junit(name: &str, diagnostics: &[Diagnostic]) -> String {
+    let diagnostics = relevant(diagnostics);
+    let errors = diagnostics.iter().filter(|d| d.level == "error").count();
+    let failures = diagnostics.len() - errors;
+
+    let mut cases: Vec<String> = diagnostics.iter().map(|d| test_case(d)).collect();
+    if cases.is_empty() {
+        cases.push(String::from("    <testcase name=\"clippy\" classname=\"clippy\"/>\n"));
+    }
+
+    format!("{header}<testsuites name=\"clippy\" tests=\"{tests}\" failures=\"{failures}\" \
+             errors=\"{errors}\">\n  <testsuite name=\"{name}\" tests=\"{tests}\" \
+             failures=\"{failures}\" errors=\"{errors}\">\n{cases}  </testsuite>\n</testsuites>\n",
+            header = XML_HEADER,
+            name = escape(name),
+            tests = cases.len(),
+            failures = failures,
+            errors = errors,
+            cases = cases.concat())
+}
+
+// A failing – or erring, for errors – test case for the diagnostic
+fn test_case(diagnostic: &Diagnostic) -> String {
+    let lint = escape(&lint(diagnostic));
+    let (location, attributes) = match diagnostic.span {
+        Some(ref span) => {
+            (format!("{}:{}:{}", span.file, span.line_start, span.column_start),
+             format!(" file=\"{}\" line=\"{}\"", escape(&span.file), span.line_start))
+        }
+        None => (String::new(), String::new()),
+    };
+    let source = diagnostic.span.as_ref().map_or(String::new(), |span| span.text.join("\n"));
+    format!("    <testcase name=\"{lint} at {location}\" classname=\"{lint}\"{attributes}>\n      \
+             <{kind} type=\"{lint}\" message=\"{message}\">{location}: {message}\n{source}</{kind}>\n    \
+             </testcase>\n",
+            lint = lint,
+            location = escape(&location),
+            attributes = attributes,
+            kind = if diagnostic.level == "error" { "error" } else { "failure" },
+            message = escape(&diagnostic.message),
+            source = escape(&source))
+}
+
+// ## Checkstyle
+// A Checkstyle XML report with a file node for every file warnings or
+// errors were found in, holding an error node for each of them. Those not
+// pointing at any file – like the compiler giving up after some errors –
+// have no place in it, so they are left out.
+pub fn checkstyle(diagnostics: &[Diagnostic]) -> String {
+    let mut files: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for diagnostic in relevant(diagnostics) {
+        let span = match diagnostic.span {
+            Some(ref span) => span,
+            None => continue,
+        };
+        files.entry(span.file.clone()).or_insert(vec![]).push(format!(
+            "    <error line=\"{}\" column=\"{}\" severity=\"{}\" message=\"{}\" source=\"clippy.{}\"/>\n",
+            span.line_start,
+            span.column_start,
+            diagnostic.level,
+            escape(&diagnostic.message),
+            escape(&lint(diagnostic))));
+    }
+
+    let files: Vec<String> = files.into_iter()
+                                  .map(|(file, errors)| {
+                                      format!("  <file name=\"{}\">\n{}  </file>\n",
+                                              escape(&file),
+                                              errors.concat())
+                                  })
+                                  .collect();
+    format!("{}<checkstyle version=\"4.3\">\n{}</checkstyle>\n",
+            XML_HEADER,
+            files.concat())
+}
+
+// ## Code Quality
+// A Code Climate JSON report, as shown by Gitlab's code quality widget.
+// Every issue has a fingerprint, which stays the same across commits as long
+// as the lint, the file and the source it points at do – so Gitlab can tell
+// which are new and which have been resolved. The same issue may be reported
+// several times in a file, those we tell apart by counting them.
+pub fn code_quality(diagnostics: &[Diagnostic]) -> Json {
+    let mut seen: HashMap<(String, String, String), usize> = HashMap::new();
+    Json::Array(relevant(diagnostics)
+                    .into_iter()
+                    .map(|diagnostic| {
+                        let (lint, file, snippet) = fingerprint(diagnostic);
+                        let occurrence = seen.entry((lint.clone(), file.clone(), snippet.clone()))
+                                             .or_insert(0);
+                        *occurrence += 1;
+                        let mut hash = Md5::new();
+                        hash.input_str(&format!("{}\0{}\0{}\0{}", lint, file, snippet, occurrence));
+
+                        let mut lines: BTreeMap<String, Json> = BTreeMap::new();
+                        lines.insert("begin".to_owned(),
+                                     Json::U64(diagnostic.span.as_ref().map_or(1, |span| span.line_start)));
+                        let mut location: BTreeMap<String, Json> = BTreeMap::new();
+                        location.insert("path".to_owned(), Json::String(file));
+                        location.insert("lines".to_owned(), Json::Object(lines));
+
+                        let mut issue: BTreeMap<String, Json> = BTreeMap::new();
+                        issue.insert("type".to_owned(), Json::String("issue".to_owned()));
+                        issue.insert("check_name".to_owned(), Json::String(lint));
+                        issue.insert("description".to_owned(), Json::String(diagnostic.message.clone()));
+                        // Errors keep the code from compiling, warnings are
+                        // about its style
+                        let (category, severity) = match diagnostic.level.as_str() {
+                            "error" => ("Bug Risk", "major"),
+                            _ => ("Style", "minor"),
+                        };
+                        issue.insert("categories".to_owned(),
+                                     Json::Array(vec![Json::String(category.to_owned())]));
+                        issue.insert("severity".to_owned(), Json::String(severity.to_owned()));
+                        issue.insert("fingerprint".to_owned(), Json::String(hash.result_str()));
+                        issue.insert("location".to_owned(), Json::Object(location));
+                        Json::Object(issue)
+                    })
+                    .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::Json;
+
+    use super::{checkstyle, code_quality, junit};
+    use clippy::{Diagnostic, Span};
+
+    // A warning of `lint` in `file` at `line`, pointing at the `source`
+    fn warning(lint: &str, file: &str, line: u64, source: &str) -> Diagnostic {
+        Diagnostic {
+            code: Some(lint.to_owned()),
+            level: String::from("warning"),
+            message: format!("{} fired", lint),
+            span: Some(Span {
+                file: file.to_owned(),
+                line_start: line,
+                line_end: line,
+                column_start: 1,
+                column_end: source.len() as u64 + 1,
+                text: vec![source.to_owned()],
+                label: None,
+            }),
+            children: vec![],
+            suggestions: vec![],
+        }
+    }
+
+    // An error without any span, like the compiler giving up
+    fn aborting() -> Diagnostic {
+        Diagnostic {
+            code: None,
+            level: String::from("error"),
+            message: String::from("aborting due to previous error"),
+            span: None,
+            children: vec![],
+            suggestions: vec![],
+        }
+    }
+
+    #[test]
+    fn escapes_messages_in_xml() {
+        let mut diagnostic = warning("clippy::needless_return", "src/lib.rs", 1, "if a < b && c");
+        diagnostic.message = String::from("use <T> & \"quotes\"");
+        let diagnostics = vec![diagnostic];
+
+        let report = junit("crate", &diagnostics);
+        assert!(report.contains("message=\"use &lt;T&gt; &amp; &quot;quotes&quot;\""));
+        assert!(report.contains("if a &lt; b &amp;&amp; c"));
+        assert!(!report.contains("<T>"));
+
+        let report = checkstyle(&diagnostics);
+        assert!(report.contains("message=\"use &lt;T&gt; &amp; &quot;quotes&quot;\""));
+    }
+
+    #[test]
+    fn reports_every_occurrence_on_its_own() {
+        let diagnostics = vec![warning("clippy::needless_return", "src/lib.rs", 10, "return x;"),
+                               warning("clippy::needless_return", "src/lib.rs", 20, "return x;"),
+                               warning("clippy::needless_return", "src/main.rs", 10, "return x;")];
+
+        let report = junit("crate", &diagnostics);
+        assert_eq!(report.matches("<testcase ").count(), 3);
+        assert!(report.contains("tests=\"3\" failures=\"3\" errors=\"0\""));
+
+        let report = checkstyle(&diagnostics);
+        assert_eq!(report.matches("<file ").count(), 2);
+        assert_eq!(report.matches("<error ").count(), 3);
+    }
+
+    #[test]
+    fn passes_without_any_diagnostics() {
+        let report = junit("crate", &[]);
+        assert_eq!(report.matches("<testcase ").count(), 1);
+        assert!(report.contains("tests=\"1\" failures=\"0\" errors=\"0\""));
+    }
+
+    #[test]
+    fn leaves_diagnostics_without_a_file_out_of_checkstyle() {
+        let diagnostics = vec![warning("clippy::needless_return", "src/lib.rs", 10, "return x;"),
+                               aborting()];
+        let report = checkstyle(&diagnostics);
+        assert_eq!(report.matches("<error ").count(), 1);
+        assert!(!report.contains("<file name=\"\">"));
+    }
+
+    // The fingerprints of the issues in the Code Climate report
+    fn fingerprints(diagnostics: &[Diagnostic]) -> Vec<String> {
+        match code_quality(diagnostics) {
+            Json::Array(issues) => {
+                issues.iter()
+                      .map(|issue| issue.find("fingerprint").unwrap().as_string().unwrap().to_owned())
+                      .collect()
+            }
+            _ => panic!("Expected an array of issues"),
+        }
+    }
+
+    #[test]
+    fn keeps_fingerprints_when_only_the_line_changes() {
+        let before = fingerprints(&[warning("clippy::needless_return", "src/lib.rs", 10, "return x;")]);
+        let after = fingerprints(&[warning("clippy::needless_return", "src/lib.rs", 14, "  return x;")]);
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn changes_fingerprints_with_the_content() {
+        let before = fingerprints(&[warning("clippy::needless_return", "src/lib.rs", 10, "return x;")]);
+        let after = fingerprints(&[warning("clippy::needless_return", "src/lib.rs", 10, "return y;")]);
+        assert!(before != after);
+    }
+
+    #[test]
+    fn tells_repeated_issues_apart() {
+        let found = fingerprints(&[warning("clippy::needless_return", "src/lib.rs", 10, "return x;"),
+                                   warning("clippy::needless_return", "src/lib.rs", 20, "return x;")]);
+        assert_eq!(found.len(), 2);
+        assert!(found[0] != found[1]);
+    }
+}
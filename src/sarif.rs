@@ -0,0 +1,251 @@
+// Export the diagnostics as SARIF 2.1.0, for code-scanning tools to ingest
+
+extern crate rustc_serialize;
+
+use std::collections::BTreeMap;
+use std::vec::Vec;
+use rustc_serialize::json::Json;
+
+use clippy::{Diagnostic, Span, Suggestion, help_url, is_error_code};
+
+// A small helper to build JSON objects from their fields
+fn object(fields: Vec<(&str, Json)>) -> Json {
+    Json::Object(fields.into_iter().map(|(key, value)| (key.to_owned(), value)).collect())
+}
+
+fn text(text: &str) -> Json {
+    object(vec![("text", Json::String(text.to_owned()))])
+}
+
+// The file the span is in, relative to the root of the repository
+fn artifact_location(span: &Span) -> Json {
+    object(vec![("uri", Json::String(span.file.clone())),
+                ("uriBaseId", Json::String("SRCROOT".to_owned()))])
+}
+
+fn region(span: &Span) -> Json {
+    object(vec![("startLine", Json::U64(span.line_start)),
+                ("startColumn", Json::U64(span.column_start)),
+                ("endLine", Json::U64(span.line_end)),
+                ("endColumn", Json::U64(span.column_end))])
+}
+
+// ## Fix
+// A suggestion as SARIF fix, described by its `message`: all of its
+// replacements together, grouped by the file they change – applying only
+// some of them would leave broken code behind.
+fn fix(message: &str, suggestions: &[Suggestion]) -> Json {
+    let mut files: Vec<(&Span, Vec<Json>)> = Vec::new();
+    for suggestion in suggestions {
+        let replacement = object(vec![("deletedRegion", region(&suggestion.span)),
+                                      ("insertedContent", text(&suggestion.replacement))]);
+        match files.iter().position(|&(span, _)| span.file == suggestion.span.file) {
+            Some(index) => files[index].1.push(replacement),
+            None => files.push((&suggestion.span, vec![replacement])),
+        }
+    }
+    let changes: Vec<Json> = files.into_iter()
+                                  .map(|(span, replacements)| {
+                                      object(vec![("artifactLocation", artifact_location(span)),
+                                                  ("replacements", Json::Array(replacements))])
+                                  })
+                                  .collect();
+    object(vec![("description", text(message)),
+                ("artifactChanges", Json::Array(changes))])
+}
+
+// ## Suggestions
+// The suggestions of the diagnostic, each with the message describing it
+// and all of its replacements: those of the diagnostic itself come first,
+// followed by those of every child – usually a `help` – and their children
+// in turn.
+fn suggestions(diagnostic: &Diagnostic) -> Vec<(&str, &[Suggestion])> {
+    let mut suggestions: Vec<(&str, &[Suggestion])> = Vec::new();
+    if !diagnostic.suggestions.is_empty() {
+        suggestions.push((&diagnostic.message, &diagnostic.suggestions));
+    }
+    for child in &diagnostic.children {
+        suggestions.extend(self::suggestions(child));
+    }
+    suggestions
+}
+
+// ## Result
+// A single diagnostic as SARIF result, pointing at its rule by index.
+// Suggestions clippy is sure about – those which are machine applicable in
+// every place – are offered as fixes.
+fn result(diagnostic: &Diagnostic, rule: &str, rule_index: usize) -> Json {
+    let level = match diagnostic.level.as_str() {
+        "error" => "error",
+        "warning" => "warning",
+        _ => "note",
+    };
+    let mut fields = vec![("ruleId", Json::String(rule.to_owned())),
+                          ("ruleIndex", Json::U64(rule_index as u64)),
+                          ("level", Json::String(level.to_owned())),
+                          ("message", text(&diagnostic.message))];
+
+    if let Some(ref span) = diagnostic.span {
+        let physical = object(vec![("artifactLocation", artifact_location(span)),
+                                   ("region", region(span))]);
+        let location = object(vec![("physicalLocation", physical)]);
+        fields.push(("locations", Json::Array(vec![location])));
+    }
+
+    let fixes: Vec<Json> = suggestions(diagnostic)
+                               .into_iter()
+                               .filter(|&(_, replacements)| {
+                                   replacements.iter().all(|replacement| {
+                                       replacement.applicability
+                                                  .as_ref()
+                                                  .map_or(false, |a| a == "MachineApplicable")
+                                   })
+                               })
+                               .map(|(message, replacements)| fix(message, replacements))
+                               .collect();
+    if !fixes.is_empty() {
+        fields.push(("fixes", Json::Array(fixes)));
+    }
+    object(fields)
+}
+
+// ## Rule Description
+// What the rule is about, the same for all of its results: a compiler error
+// code, a lint – clippy's or the compiler's – or a plain compiler message,
+// without either, of the given `level`.
+fn rule_description(rule: &str, level: &str, has_code: bool) -> String {
+    if !has_code {
+        format!("Compiler {}", level)
+    } else if is_error_code(rule) {
+        format!("Compiler error {}", rule)
+    } else {
+        format!("Lint `{}`", rule)
+    }
+}
+
+// ## To SARIF
+// Convert the warnings and errors into a SARIF log with a single run of
+// clippy, whose rules are the lints reported. The `version` is that of the
+// toolchain used, if known.
+pub fn to_sarif(diagnostics: &[Diagnostic], version: Option<&str>) -> Json {
+    let mut rules: Vec<Json> = Vec::new();
+    let mut rule_indices: BTreeMap<String, usize> = BTreeMap::new();
+    let mut results: Vec<Json> = Vec::new();
+
+    for diagnostic in diagnostics.iter().filter(|d| d.level == "warning" || d.level == "error") {
+        let rule = diagnostic.code.clone().unwrap_or(diagnostic.level.clone());
+        let index = match rule_indices.get(&rule) {
+            Some(&index) => index,
+            None => {
+                let mut fields = vec![("id", Json::String(rule.clone())),
+                                      ("name", Json::String(rule.clone())),
+                                      ("shortDescription",
+                                       text(&rule_description(&rule,
+                                                              &diagnostic.level,
+                                                              diagnostic.code.is_some())))];
+                if diagnostic.code.is_some() {
+                    fields.push(("helpUri", Json::String(help_url(&rule))));
+                }
+                rules.push(object(fields));
+                rule_indices.insert(rule.clone(), rules.len() - 1);
+                rules.len() - 1
+            }
+        };
+        results.push(result(diagnostic, &rule, index));
+    }
+
+    let mut driver = vec![("name", Json::String("clippy".to_owned())),
+                          ("informationUri",
+                           Json::String("https://github.com/rust-lang/rust-clippy".to_owned())),
+                          ("rules", Json::Array(rules))];
+    if let Some(version) = version {
+        driver.push(("version", Json::String(version.to_owned())));
+    }
+    let run = object(vec![("tool", object(vec![("driver", object(driver))])),
+                          ("results", Json::Array(results))]);
+
+    object(vec![("$schema", Json::String("https://json.schemastore.org/sarif-2.1.0.json".to_owned())),
+                ("version", Json::String("2.1.0".to_owned())),
+                ("runs", Json::Array(vec![run]))])
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::Json;
+
+    use super::to_sarif;
+    use clippy::{Diagnostic, Span, Suggestion};
+
+    // A span in `src/lib.rs` at `line`
+    fn span(line: u64) -> Span {
+        Span {
+            file: String::from("src/lib.rs"),
+            line_start: line,
+            line_end: line,
+            column_start: 5,
+            column_end: 13,
+            text: vec![String::from("    return x;")],
+            label: None,
+        }
+    }
+
+    // A warning of `lint` at `line`, with a `help` child suggesting to
+    // replace it – with the given applicability
+    fn warning(lint: &str, line: u64, applicability: &str) -> Diagnostic {
+        let help = Diagnostic {
+            code: None,
+            level: String::from("help"),
+            message: String::from("remove `return`"),
+            span: Some(span(line)),
+            children: vec![],
+            suggestions: vec![Suggestion {
+                                  span: span(line),
+                                  replacement: String::from("x"),
+                                  applicability: Some(applicability.to_owned()),
+                              }],
+        };
+        Diagnostic {
+            code: Some(lint.to_owned()),
+            level: String::from("warning"),
+            message: format!("{} fired", lint),
+            span: Some(span(line)),
+            children: vec![help],
+            suggestions: vec![],
+        }
+    }
+
+    fn run(sarif: &Json) -> &Json {
+        &sarif.find("runs").unwrap().as_array().unwrap()[0]
+    }
+
+    #[test]
+    fn lists_every_rule_once() {
+        let sarif = to_sarif(&[warning("clippy::needless_return", 1, "MachineApplicable"),
+                               warning("clippy::let_and_return", 2, "MachineApplicable"),
+                               warning("clippy::needless_return", 3, "MachineApplicable")],
+                             None);
+        let rules = run(&sarif).find_path(&["tool", "driver", "rules"]).unwrap().as_array().unwrap();
+        let ids: Vec<&str> = rules.iter().map(|rule| rule.find("id").unwrap().as_string().unwrap()).collect();
+        assert_eq!(ids, vec!["clippy::needless_return", "clippy::let_and_return"]);
+
+        let results = run(&sarif).find("results").unwrap().as_array().unwrap();
+        let indices: Vec<u64> = results.iter()
+                                       .map(|result| result.find("ruleIndex").unwrap().as_u64().unwrap())
+                                       .collect();
+        assert_eq!(indices, vec![0, 1, 0]);
+    }
+
+    #[test]
+    fn offers_only_machine_applicable_suggestions_as_fixes() {
+        let sarif = to_sarif(&[warning("clippy::needless_return", 1, "MachineApplicable"),
+                               warning("clippy::needless_return", 2, "MaybeIncorrect")],
+                             None);
+        let results = run(&sarif).find("results").unwrap().as_array().unwrap();
+
+        let fixes = results[0].find("fixes").unwrap().as_array().unwrap();
+        assert_eq!(fixes.len(), 1);
+        assert_eq!(fixes[0].find_path(&["description", "text"]).unwrap().as_string(),
+                   Some("remove `return`"));
+        assert!(results[1].find("fixes").is_none());
+    }
+}
@@ -0,0 +1,175 @@
+// Follow the log of a lint live, as Server-Sent Events
+
+extern crate iron;
+extern crate redis;
+
+use std::env;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::time::{Duration, Instant};
+use std::vec::Vec;
+use iron::response::{ResponseBody, WriteBody};
+use redis::{Commands, RedisError};
+
+use helpers::redis_client;
+
+// How long we wait for the next line, before sending a comment to keep the
+// connection alive
+const KEEPALIVE_SECONDS: u64 = 15;
+
+// How long a single stream is followed at most, before the client has to
+// reconnect
+const MAX_STREAM_SECONDS: u64 = 60;
+
+// The streams currently open, across all handler threads
+static OPEN_STREAMS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// ## Max Streams
+// How many streams may be open at once, configured via the `MAX_STREAMS`
+// environment variable. Every stream keeps one of the web server's threads
+// busy, so this has to stay well below their number.
+fn max_streams() -> usize {
+    env::var("MAX_STREAMS").ok().and_then(|value| value.parse().ok()).unwrap_or(4)
+}
+
+// ## Event Stream
+// The body of the response streaming the log stored under `log/{key}`: first
+// the lines logged so far – after the one with id `last_id`, if the client
+// reconnects – then every line as it is logged, ending with the `verdict`
+// event carrying the result once linting is done. Every line has its position
+// in the log as id.
+pub struct EventStream {
+    key: String,
+    last_id: usize,
+}
+
+impl EventStream {
+    // Take one of the slots for open streams, or `None` if all are taken.
+    // The slot is given back once the stream is dropped.
+    pub fn new(key: &str, last_id: usize) -> Option<EventStream> {
+        if OPEN_STREAMS.fetch_add(1, Ordering::SeqCst) >= max_streams() {
+            OPEN_STREAMS.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(EventStream {
+            key: key.to_owned(),
+            last_id: last_id,
+        })
+    }
+
+    // Send the lines of the log from `last_id` up to (including) `until`,
+    // or to its end.
+    fn catch_up(&mut self,
+                redis: &redis::Connection,
+                until: Option<usize>,
+                res: &mut ResponseBody)
+                -> io::Result<()> {
+        let end = until.map_or(-1, |until| until as isize - 1);
+        let lines: Vec<String> = redis.lrange(format!("log/{}", self.key), self.last_id as isize, end)
+                                      .unwrap_or(vec![]);
+        for line in lines {
+            self.last_id += 1;
+            try!(res.write_all(event(Some(self.last_id), None, &line).as_bytes()));
+        }
+        res.flush()
+    }
+
+    // Catch up with the log and look for the result, which is there once
+    // linting is done.
+    fn verdict(&mut self,
+               redis: &redis::Connection,
+               res: &mut ResponseBody)
+               -> io::Result<Option<String>> {
+        try!(self.catch_up(redis, None, res));
+        Ok(redis.get(format!("result/{}", self.key)).unwrap_or(None))
+    }
+}
+
+impl WriteBody for EventStream {
+    fn write_body(&mut self, res: &mut ResponseBody) -> io::Result<()> {
+        // We subscribe before reading the log, so we don't miss any line
+        // logged in between. Those we already sent are told apart by their
+        // position.
+        let client = redis_client();
+        let mut pubsub = try!(client.get_pubsub().map_err(io_error));
+        try!(pubsub.subscribe(format!("log/{}", self.key)).map_err(io_error));
+        try!(pubsub.subscribe(format!("done/{}", self.key)).map_err(io_error));
+        try!(pubsub.set_read_timeout(Some(Duration::from_secs(KEEPALIVE_SECONDS))).map_err(io_error));
+        let redis = try!(client.get_connection().map_err(io_error));
+
+        if let Some(verdict) = try!(self.verdict(&redis, res)) {
+            return res.write_all(event(None, Some("verdict"), &verdict).as_bytes());
+        }
+
+        // Every stream keeps a thread busy, so we only follow a log for a
+        // minute. Clients still interested reconnect – which `EventSource`
+        // does by itself – and carry on where they left off.
+        let deadline = Instant::now() + Duration::from_secs(MAX_STREAM_SECONDS);
+        while Instant::now() < deadline {
+            let message = match pubsub.get_message() {
+                Ok(message) => message,
+                // Nothing happened for a while: make sure the client is still
+                // there – writing fails otherwise – and that we didn't miss
+                // the job finishing.
+                Err(ref error) if error.is_timeout() => {
+                    try!(res.write_all(b": keepalive\n\n"));
+                    if let Some(verdict) = try!(self.verdict(&redis, res)) {
+                        return res.write_all(event(None, Some("verdict"), &verdict).as_bytes());
+                    }
+                    continue;
+                }
+                Err(error) => return Err(io_error(error)),
+            };
+            let payload: String = try!(message.get_payload().map_err(io_error));
+            if message.get_channel_name().starts_with("done/") {
+                // Make sure the client has seen every line before the verdict
+                try!(self.catch_up(&redis, None, res));
+                return res.write_all(event(None, Some("verdict"), &payload).as_bytes());
+            }
+
+            let mut parts = payload.splitn(2, '\t');
+            if let (Some(Ok(position)), Some(line)) = (parts.next().map(|p| p.parse::<usize>()),
+                                                      parts.next()) {
+                if position > self.last_id + 1 {
+                    // Some lines were logged without being published, e.g.
+                    // when the job was queued. Fetch those first.
+                    try!(self.catch_up(&redis, Some(position - 1), res));
+                }
+                if position == self.last_id + 1 {
+                    self.last_id = position;
+                    try!(res.write_all(event(Some(position), None, line).as_bytes()));
+                    try!(res.flush());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        OPEN_STREAMS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// ## Event
+// A single Server-Sent Event, with optional `id` and `name`. Every line of
+// the `data` needs its own field.
+fn event(id: Option<usize>, name: Option<&str>, data: &str) -> String {
+    let mut event = String::new();
+    if let Some(id) = id {
+        event.push_str(&format!("id: {}\n", id));
+    }
+    if let Some(name) = name {
+        event.push_str(&format!("event: {}\n", name));
+    }
+    for line in data.split('\n') {
+        event.push_str(&format!("data: {}\n", line));
+    }
+    event.push('\n');
+    event
+}
+
+fn io_error(error: RedisError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("{}", error))
+}
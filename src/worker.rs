@@ -0,0 +1,31 @@
+// **Run as `clippy-worker`, this is where execution starts.**
+//
+// The worker takes the jobs the web server puts into the redis queue, lints
+// them and stores their results and logs – just like the workers the web
+// server starts itself. Running them as separate processes allows for scaling
+// the web front and the builds independently, and restarting the web server
+// without killing any build.
+
+extern crate clippy_service;
+extern crate env_logger;
+
+#[macro_use]
+extern crate log;
+
+use clippy_service::jobs;
+
+fn main() {
+    // setup logger using [env_logger](http://doc.rust-lang.org/log/env_logger/index.html) crate.
+    // Thus, you can specify the log output with the handy `RUST_LOG` environment variable
+    env_logger::init().unwrap();
+
+    // How many jobs are processed at the same time can be configured via the
+    // `WORKERS` environment variable, the redis to take them from via `REDIS_URL`.
+    let count = jobs::worker_count();
+    warn!("Worker running with {} threads", count);
+
+    // The workers never stop on their own, so this keeps us running
+    for worker in jobs::start_workers(count) {
+        let _ = worker.join();
+    }
+}
@@ -0,0 +1,243 @@
+// Find the crates to lint in the files we checked out – be it a single
+// crate or all members of a cargo workspace.
+
+extern crate toml;
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::vec::Vec;
+
+use clippy::{Report, RunError};
+
+// The results of linting every member, by their name
+pub type MemberResults = Vec<(String, Result<Report, RunError>)>;
+
+// ## Member
+// A crate we are going to lint: its package name, which we also use to
+// identify its results, and the directory its `Cargo.toml` is in.
+pub struct Member {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+// ## Find Root Manifest
+// Of all the `Cargo.toml` files extracted, the one closest to the root of the
+// checkout is the one describing the project – others usually belong to
+// workspace members, examples or test fixtures. We match the file name
+// exactly, as cargo does, so a `cargo.toml` fixture isn't picked up.
+pub fn find_root_manifest(files: &[String]) -> Option<PathBuf> {
+    files.iter()
+         .map(|file| PathBuf::from(file))
+         .filter(|path| path.file_name().map_or(false, |name| name == "Cargo.toml"))
+         .min_by_key(|path| path.components().count())
+}
+
+// ## Find Manifest
+// Find the `Cargo.toml` at exactly the path asked for below the `root` of the
+// repository. It has to be a file – not a symlink – and, once all links in
+// the folders leading there are resolved, still be within the repository.
+pub fn find_manifest(root: &Path, manifest: &str) -> Option<PathBuf> {
+    if !is_valid_manifest_path(manifest) {
+        return None;
+    }
+    let path = root.join(manifest);
+    let is_file = fs::symlink_metadata(&path).map(|metadata| metadata.is_file()).unwrap_or(false);
+    match (fs::canonicalize(root), fs::canonicalize(&path)) {
+        (Ok(ref root), Ok(ref resolved)) if is_file && resolved.starts_with(root) => Some(path),
+        _ => None,
+    }
+}
+
+// ## Valid Manifest Path
+// Manifest paths are given by the user, so make sure they point to a
+// `Cargo.toml` file and stay within the repository: a relative path of plain
+// folder names, without any `.`, `..` or empty parts.
+pub fn is_valid_manifest_path(manifest: &str) -> bool {
+    let path = Path::new(manifest);
+    path.file_name().map_or(false, |name| name == "Cargo.toml") &&
+    manifest.split('/').all(|part| !part.is_empty() && part != "." && part != "..") &&
+    path.components().all(|component| match component {
+        Component::Normal(_) => true,
+        _ => false,
+    })
+}
+
+// ## Read Manifest
+// Parse the `Cargo.toml` file at `path` into a toml value.
+fn read_manifest(path: &Path) -> Result<toml::Value, String> {
+    let mut contents = String::new();
+    try!(File::open(path)
+             .and_then(|mut file| file.read_to_string(&mut contents))
+             .map_err(|error| format!("Couldn't read {}: {}", path.display(), error)));
+    match toml::Parser::new(&contents).parse() {
+        Some(table) => Ok(toml::Value::Table(table)),
+        None => Err(format!("Couldn't parse {}", path.display())),
+    }
+}
+
+// ## Package Name
+// Read the name of the package from the manifest in `dir`, if it has one.
+fn package_name(dir: &Path) -> Option<String> {
+    read_manifest(&dir.join("Cargo.toml"))
+        .ok()
+        .and_then(|manifest| {
+            manifest.lookup("package.name").and_then(|name| name.as_str()).map(String::from)
+        })
+}
+
+// ## Expand Member
+// Workspace members are paths relative to the root, which may end in a `*`
+// to include every crate in that folder, e.g. `crates/*`. Any other glob is
+// refused, rather than silently linting something else than cargo would.
+fn expand_member(root: &Path, member: &str) -> Result<Vec<PathBuf>, String> {
+    let is_glob = |path: &str| path.contains(|c: char| c == '*' || c == '?' || c == '[');
+    if member.ends_with("/*") && !is_glob(member.trim_right_matches("/*")) {
+        let parent = root.join(member.trim_right_matches("/*"));
+        match fs::read_dir(&parent) {
+            Ok(entries) => {
+                let mut dirs: Vec<PathBuf> = entries.filter_map(|entry| entry.ok())
+                                                    .map(|entry| entry.path())
+                                                    .filter(|path| path.join("Cargo.toml").is_file())
+                                                    .collect();
+                dirs.sort();
+                Ok(dirs)
+            }
+            Err(_) => Ok(vec![]),
+        }
+    } else if is_glob(member) {
+        Err(format!("Unsupported workspace member {}, only `path/*` globs are supported", member))
+    } else {
+        Ok(vec![root.join(member)])
+    }
+}
+
+// ## Members
+// Given the root manifest, find all crates to lint. For a workspace these
+// are all `[workspace] members` (and the root package, if there is one),
+// leaving out those below any of the `exclude`d paths, otherwise it is just
+// the one crate.
+pub fn members(root_manifest: &Path) -> Result<Vec<Member>, String> {
+    let root = root_manifest.parent().unwrap();
+    let manifest = try!(read_manifest(root_manifest));
+    let paths = |name: &str| -> Vec<String> {
+        manifest.lookup(name)
+                .and_then(|paths| paths.as_slice())
+                .map(|paths| paths.iter().filter_map(|path| path.as_str()).map(String::from).collect())
+                .unwrap_or(vec![])
+    };
+
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    if manifest.lookup("package").is_some() {
+        dirs.push(root.to_path_buf());
+    }
+    for member in paths("workspace.members") {
+        dirs.extend(try!(expand_member(root, &member)));
+    }
+    let excluded: Vec<PathBuf> = paths("workspace.exclude").iter().map(|path| root.join(path)).collect();
+    dirs.retain(|dir| dir.as_path() == root || !excluded.iter().any(|path| dir.starts_with(path)));
+
+    let members: Vec<Member> = dirs.into_iter()
+                                   .filter_map(|dir| {
+                                       package_name(&dir).map(|name| {
+                                           Member {
+                                               name: name,
+                                               path: dir,
+                                           }
+                                       })
+                                   })
+                                   .collect();
+    if members.is_empty() {
+        Err(format!("No crate found in {}", root_manifest.display()))
+    } else {
+        Ok(members)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::Path;
+    use tempdir::TempDir;
+
+    use super::{find_manifest, is_valid_manifest_path, members};
+
+    // Write the `contents` to the file at `path` below `root`
+    fn write(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(path).unwrap().write_all(contents.as_bytes()).unwrap();
+    }
+
+    // A crate named `name` in `dir` below `root`
+    fn package(root: &Path, dir: &str, name: &str) {
+        write(root, &format!("{}/Cargo.toml", dir), &format!("[package]\nname = \"{}\"\n", name));
+    }
+
+    #[test]
+    fn accepts_manifest_paths_within_the_repository() {
+        assert!(is_valid_manifest_path("Cargo.toml"));
+        assert!(is_valid_manifest_path("crates/core/Cargo.toml"));
+    }
+
+    #[test]
+    fn rejects_manifest_paths_leaving_the_repository() {
+        assert!(!is_valid_manifest_path("../Cargo.toml"));
+        assert!(!is_valid_manifest_path("crates/../../Cargo.toml"));
+        assert!(!is_valid_manifest_path("/Cargo.toml"));
+        assert!(!is_valid_manifest_path("/etc/crate/Cargo.toml"));
+    }
+
+    #[test]
+    fn rejects_manifest_paths_with_empty_or_current_parts() {
+        assert!(!is_valid_manifest_path("./Cargo.toml"));
+        assert!(!is_valid_manifest_path("rust/./Cargo.toml"));
+        assert!(!is_valid_manifest_path("rust//Cargo.toml"));
+        assert!(!is_valid_manifest_path("rust/Cargo.toml/"));
+    }
+
+    #[test]
+    fn finds_manifests_exactly_below_the_root() {
+        let root = TempDir::new("workspace").unwrap();
+        package(root.path(), "rust", "core");
+        package(root.path(), "vendor/rust", "vendored");
+
+        assert_eq!(find_manifest(root.path(), "rust/Cargo.toml"),
+                   Some(root.path().join("rust/Cargo.toml")));
+        assert_eq!(find_manifest(root.path(), "Cargo.toml"), None);
+        assert_eq!(find_manifest(root.path(), "other/rust/Cargo.toml"), None);
+        assert_eq!(find_manifest(root.path(), "./rust/Cargo.toml"), None);
+    }
+
+    #[test]
+    fn rejects_anything_but_manifests() {
+        assert!(!is_valid_manifest_path("Cargo.lock"));
+        assert!(!is_valid_manifest_path("crates/core/cargo.toml"));
+        assert!(!is_valid_manifest_path("crates/core"));
+    }
+
+    #[test]
+    fn leaves_out_excluded_members() {
+        let root = TempDir::new("workspace").unwrap();
+        write(root.path(),
+              "Cargo.toml",
+              "[workspace]\nmembers = [\"crates/*\", \"tools/gen\"]\nexclude = [\"crates/broken\"]\n");
+        package(root.path(), "crates/core", "core");
+        package(root.path(), "crates/broken", "broken");
+        package(root.path(), "tools/gen", "gen");
+
+        let found = members(&root.path().join("Cargo.toml")).unwrap();
+        let names: Vec<&str> = found.iter().map(|member| member.name.as_str()).collect();
+        assert_eq!(names, vec!["core", "gen"]);
+    }
+
+    #[test]
+    fn refuses_other_globs() {
+        let root = TempDir::new("workspace").unwrap();
+        write(root.path(), "Cargo.toml", "[workspace]\nmembers = [\"crates/core-*\"]\n");
+        package(root.path(), "crates/core-a", "core-a");
+
+        assert!(members(&root.path().join("Cargo.toml")).is_err());
+    }
+}